@@ -1,15 +1,167 @@
-use super::Table;
+use std::cell::{Cell, RefCell};
+use std::intrinsics::TypeId;
+
+use super::{Table, TableList, SqlValue, NullValue, IntValue, TextValue, FloatValue};
+use dialect;
+use dialect::SqlDialect;
+use migrate;
 use selector::ColumnFacade;
+use sqlite;
 use sqlite3;
 
+// A column as declared in the live database.
+#[deriving(Clone, PartialEq, Show)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub typename: String,
+    pub notnull: bool,
+    pub default: Option<String>,
+    pub primary_key: bool
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum SqlError {
+    UniqueViolation { pub table: String, pub columns: Vec<String> },
+    CheckViolation { pub table: String, pub constraint: String },
+    SqlFailure(String)
+}
+
+// Maps the message of a failed constraint to a `SqlError`. SQLite reports
+// either `UNIQUE constraint failed: T.a, T.b` or `columns a, b are not unique`,
+// and `CHECK constraint failed: name` for the constraints `#[sql_check]` names.
+// Versions before 3.8.3 report the latter as `constraint name failed`, or as
+// `constraint failed` without the name, which leaves `constraint` empty.
+pub fn constraint_error(table: &str, msg: &str) -> SqlError {
+    if msg.starts_with("UNIQUE constraint failed: ") {
+        let columns = msg.slice_from("UNIQUE constraint failed: ".len());
+        let mut table = table.to_string();
+        let columns = columns.split_str(", ").map(|qualified| {
+            match qualified.find('.') {
+                Some(dot) => {
+                    table = qualified.slice_to(dot).to_string();
+                    qualified.slice_from(dot + 1).to_string()
+                },
+                None => qualified.to_string()
+            }
+        }).collect();
+        UniqueViolation { table: table, columns: columns }
+    } else if msg.starts_with("CHECK constraint failed: ") {
+        CheckViolation {
+            table: table.to_string(),
+            constraint: msg.slice_from("CHECK constraint failed: ".len()).to_string()
+        }
+    } else if msg == "constraint failed" {
+        CheckViolation { table: table.to_string(), constraint: String::new() }
+    } else if msg.starts_with("constraint ") && msg.ends_with(" failed") {
+        CheckViolation {
+            table: table.to_string(),
+            constraint: msg.slice("constraint ".len(), msg.len() - " failed".len()).to_string()
+        }
+    } else if msg.ends_with(" not unique") {
+        let words: Vec<&str> = msg.words().collect();
+        let columns = words.slice(1, words.len() - 3).iter().map(|col| {
+            col.trim_right_chars(',').to_string()
+        }).collect();
+        UniqueViolation { table: table.to_string(), columns: columns }
+    } else {
+        SqlFailure(msg.to_string())
+    }
+}
+
+// Runs the inserts of `insert_many` under a savepoint, which also nests in a
+// transaction of the caller, and rolls them all back if one fails. Backends
+// where a savepoint needs a transaction that outlives the statement ask to
+// `begin` one when the caller has none.
+pub fn with_savepoint<A: SqlAdapter>(db: &A, begin: bool, insert: || -> Result<(), SqlError>)
+        -> Result<(), SqlError> {
+    if begin {
+        match db.try_execute("BEGIN;") {
+            Ok(()) => (),
+            Err(msg) => return Err(SqlFailure(msg))
+        }
+    }
+    let mut result = match db.try_execute("SAVEPOINT insert_many;") {
+        Ok(()) => insert(),
+        Err(msg) => Err(SqlFailure(msg))
+    };
+    if result.is_err() {
+        let _ = db.try_execute("ROLLBACK TO SAVEPOINT insert_many;");
+    }
+    match db.try_execute("RELEASE SAVEPOINT insert_many;") {
+        Err(msg) if result.is_ok() => result = Err(SqlFailure(msg)),
+        _ => ()
+    }
+    if begin {
+        match db.try_execute(if result.is_ok() { "COMMIT;" } else { "ROLLBACK;" }) {
+            Err(msg) if result.is_ok() => result = Err(SqlFailure(msg)),
+            _ => ()
+        }
+    }
+    result
+}
+
 pub trait SqlAdapter {
+    fn dialect(&self) -> &'static SqlDialect;
+    // Runs `query`, returning the database's message if it fails.
+    fn try_execute(&self, query: &str) -> Result<(), String>;
+    fn execute_params(&self, query: &str, params: &[SqlValue]);
+    fn table_columns(&self, table: &str) -> Vec<ColumnInfo>;
+    fn table_exists<T:Table>(&self) -> bool;
     fn create_table_if_not_exists<T:Table>(&self);
-    fn insert_many<'r, T:Table, Iter: Iterator<&'r T>>(&self, records: Iter);
-    unsafe fn select_table<'r, T>(&'r self, query: &str) -> SqlTableIter<'r, T>;
-    unsafe fn select_columns<'r, T>(&'r self, query: &str) -> SqlSelectIter<'r, T>;
+    fn insert_many<'r, T:Table, Iter: Iterator<&'r T>>(&self, records: Iter) -> Result<(), SqlError>;
+    unsafe fn select_table<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlTableIter<'r, T>;
+    unsafe fn select_columns<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlSelectIter<'r, T>;
+
+    // Where this connection binds the table of the type `key` in place of
+    // its `#[sql_schema]`, if it does, `Some(None)` being the main database.
+    fn schema_binding(&self, _: TypeId) -> Option<Option<String>> {
+        None
+    }
+
+    fn schema<T:Table>(&self) -> Option<String> {
+        match self.schema_binding(super::type_key::<T>()) {
+            Some(schema) => schema,
+            None => super::schema::<T>()
+        }
+    }
+
+    fn execute(&self, query: &str) {
+        match self.try_execute(query) {
+            Ok(()) => (),
+            Err(msg) => fail!("{}", msg)
+        }
+    }
 
     fn select_all<'r, T:Table>(&'r self) -> SqlTableIter<'r, T> {
-        unsafe { self.select_table(super::select_query::<T>()) }
+        unsafe { self.select_table(dialect::select_query::<T>(self.dialect(), self.schema::<T>()).as_slice(), []) }
+    }
+
+    // Creates each table of a tuple type, in order.
+    fn create_tables<L:TableList>(&self) {
+        TableList::create_tables(None::<&L>, self)
+    }
+
+    fn drop_table<T:Table>(&self) {
+        let table = dialect::qualified_table::<T>(self.dialect(), self.schema::<T>());
+        self.execute(format!("DROP TABLE {};", table).as_slice())
+    }
+
+    fn drop_table_if_exists<T:Table>(&self) {
+        let table = dialect::qualified_table::<T>(self.dialect(), self.schema::<T>());
+        self.execute(format!("DROP TABLE IF EXISTS {};", table).as_slice())
+    }
+
+    fn delete_all<T:Table>(&self) {
+        let table = dialect::qualified_table::<T>(self.dialect(), self.schema::<T>());
+        self.execute(format!("DELETE FROM {};", table).as_slice())
+    }
+
+    fn migrate<T:Table>(&self) -> Result<migrate::MigrationReport, migrate::MigrationError> {
+        migrate::migrate_table::<Self, T>(self)
+    }
+
+    fn verify_schema<T:Table>(&self) -> Vec<migrate::SchemaProblem> {
+        migrate::verify_table::<Self, T>(self)
     }
 }
 
@@ -27,11 +179,30 @@ pub trait SqlAdapterCursor {
     fn fetch_row(&self) -> bool;
 }
 
+pub fn bind_params(cursor: &SqlAdapterCursor, params: &[SqlValue]) {
+    for (idx, param) in params.iter().enumerate() {
+        param.bind(cursor, (idx+1) as int);
+    }
+}
+
 pub struct SqlTableIter<'r, T> {
     db: &'r SqlAdapter,
     cursor: Box<SqlAdapterCursor>
 }
 
+impl<'r, T> SqlTableIter<'r, T> {
+    pub fn new(db: &'r SqlAdapter, cursor: Box<SqlAdapterCursor>) -> SqlTableIter<'r, T> {
+        SqlTableIter {
+            db: db,
+            cursor: cursor
+        }
+    }
+
+    pub fn into_cursor(self) -> Box<SqlAdapterCursor> {
+        self.cursor
+    }
+}
+
 impl<'r, T:Table> Iterator<T> for SqlTableIter<'r, T> {
     fn next(&mut self) -> Option<T> {
         if self.cursor.fetch_row() {
@@ -57,6 +228,130 @@ impl<'r, T:ColumnFacade> Iterator<T> for SqlSelectIter<'r, T> {
     }
 }
 
+impl<'r, T> SqlSelectIter<'r, T> {
+    pub fn new(db: &'r SqlAdapter, cursor: Box<SqlAdapterCursor>) -> SqlSelectIter<'r, T> {
+        SqlSelectIter {
+            db: db,
+            cursor: cursor
+        }
+    }
+
+    pub fn into_cursor(self) -> Box<SqlAdapterCursor> {
+        self.cursor
+    }
+
+    pub fn map_rows<U>(self, get_row: fn(&SqlAdapterCursor) -> U) -> SqlRowIter<'r, U> {
+        SqlRowIter {
+            db: self.db,
+            cursor: self.cursor,
+            get_row: get_row
+        }
+    }
+}
+
+pub struct SqlRowIter<'r, T> {
+    db: &'r SqlAdapter,
+    cursor: Box<SqlAdapterCursor>,
+    get_row: fn(&SqlAdapterCursor) -> T
+}
+
+impl<'r, T> Iterator<T> for SqlRowIter<'r, T> {
+    fn next(&mut self) -> Option<T> {
+        if self.cursor.fetch_row() {
+            Some((self.get_row)(self.cursor))
+        } else {
+            None
+        }
+    }
+}
+
+// A cursor over rows already fetched as `SqlValue`s, which keeps the
+// parameters bound to it.
+pub struct ValueCursor {
+    params: RefCell<Vec<SqlValue>>,
+    rows: Vec<Vec<SqlValue>>,
+    fetched: Cell<uint>
+}
+
+impl ValueCursor {
+    pub fn new(rows: Vec<Vec<SqlValue>>) -> ValueCursor {
+        ValueCursor {
+            params: RefCell::new(Vec::new()),
+            rows: rows,
+            fetched: Cell::new(0)
+        }
+    }
+
+    // The parameters bound so far, in order.
+    pub fn params(&self) -> Vec<SqlValue> {
+        self.params.borrow().clone()
+    }
+
+    fn bind(&self, idx: int, value: SqlValue) {
+        let mut params = self.params.borrow_mut();
+        while params.len() < idx as uint {
+            params.push(NullValue);
+        }
+        *params.get_mut(idx as uint - 1) = value;
+    }
+
+    fn value<'r>(&'r self, idx: int) -> &'r SqlValue {
+        self.rows.get(self.fetched.get() - 1).get(idx as uint)
+    }
+}
+
+impl SqlAdapterCursor for ValueCursor {
+    fn bind_null(&self, idx: int) {
+        self.bind(idx, NullValue)
+    }
+
+    fn bind_int(&self, idx: int, value: int) {
+        self.bind(idx, IntValue(value))
+    }
+
+    fn bind_str(&self, idx: int, value: &str) {
+        self.bind(idx, TextValue(value.to_string()))
+    }
+
+    fn bind_f64(&self, idx: int, value: f64) {
+        self.bind(idx, FloatValue(value))
+    }
+
+    fn is_null(&self, idx: int) -> bool {
+        *self.value(idx) == NullValue
+    }
+
+    fn get_prim_int(&self, idx: int) -> int {
+        match *self.value(idx) {
+            IntValue(v) => v,
+            ref v => fail!("unexpected value {} at column {}", v, idx)
+        }
+    }
+
+    fn get_prim_str(&self, idx: int) -> String {
+        match *self.value(idx) {
+            TextValue(ref v) => v.clone(),
+            ref v => fail!("unexpected value {} at column {}", v, idx)
+        }
+    }
+
+    fn get_prim_f64(&self, idx: int) -> f64 {
+        match *self.value(idx) {
+            FloatValue(v) => v,
+            ref v => fail!("unexpected value {} at column {}", v, idx)
+        }
+    }
+
+    fn fetch_row(&self) -> bool {
+        if self.fetched.get() < self.rows.len() {
+            self.fetched.set(self.fetched.get() + 1);
+            true
+        } else {
+            false
+        }
+    }
+}
+
 impl<'db> SqlAdapterCursor for sqlite3::Cursor<'db> {
     fn bind_null(&self, idx: int) {
         match self.bind_param(idx, &sqlite3::Null) {
@@ -123,45 +418,136 @@ impl<'db> SqlAdapterCursor for sqlite3::Cursor<'db> {
     }
 }
 
+static SQLITE_DIALECT: sqlite::SqliteDialect = sqlite::SqliteDialect;
+
+// Looks the table of `T` up in the `sqlite_master` of the schema `db` binds it to.
+pub fn sqlite_table_exists<A: SqlAdapter, T: Table>(db: &A) -> bool {
+    let master = match db.schema::<T>() {
+        Some(schema) => format!("{}.sqlite_master", schema),
+        None => "sqlite_master".to_string()
+    };
+    let query = format!("SELECT count(*) FROM {} WHERE type = 'table' AND name = ?;", master);
+    let params = [TextValue(super::table_name::<T>().to_string())];
+    let mut rows: SqlSelectIter<(int,)> = unsafe { db.select_columns(query.as_slice(), params.as_slice()) };
+    match rows.next() {
+        Some((count,)) => count > 0,
+        None => false
+    }
+}
+
+// A connection of `sqlite3::open` leaves foreign key constraints off, as
+// SQLite does by default, so `#[sql_references]` is only enforced on the
+// connections of `sql::sqlite::open` and on those which ran
+// `PRAGMA foreign_keys = ON;` themselves.
 impl SqlAdapter for sqlite3::Database {
+    fn dialect(&self) -> &'static SqlDialect {
+        &SQLITE_DIALECT as &'static SqlDialect
+    }
+
+    fn try_execute(&self, query: &str) -> Result<(), String> {
+        match self.exec(query) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(self.get_errmsg())
+        }
+    }
+
+    fn execute_params(&self, query: &str, params: &[SqlValue]) {
+        match self.prepare(query, &None) {
+            Err(_) => fail!("{}", self.get_errmsg()),
+            Ok(cursor) => {
+                bind_params(&cursor, params);
+                match cursor.step() {
+                    sqlite3::SQLITE_DONE | sqlite3::SQLITE_ROW => (),
+                    _ => fail!("{}", self.get_errmsg())
+                }
+            }
+        }
+    }
+
+    fn table_columns(&self, table: &str) -> Vec<ColumnInfo> {
+        // Tables of an attached database are given as `schema.table`.
+        let query = match table.find('.') {
+            Some(dot) => format!("PRAGMA {}.table_info({});", table.slice_to(dot), table.slice_from(dot + 1)),
+            None => format!("PRAGMA table_info({});", table)
+        };
+        match self.prepare(query.as_slice(), &None) {
+            Err(_) => fail!("{}", self.get_errmsg()),
+            Ok(cursor) => {
+                let mut columns = Vec::new();
+                while cursor.fetch_row() {
+                    columns.push(ColumnInfo {
+                        name: cursor.get_text(1),
+                        typename: cursor.get_text(2),
+                        notnull: cursor.get_int(3) != 0,
+                        default: if cursor.is_null(4) { None } else { Some(cursor.get_text(4)) },
+                        primary_key: cursor.get_int(5) != 0
+                    });
+                }
+                columns
+            }
+        }
+    }
+
+    fn table_exists<T:Table>(&self) -> bool {
+        sqlite_table_exists::<sqlite3::Database, T>(self)
+    }
+
     fn create_table_if_not_exists<T:Table>(&self) {
-        let query = super::create_table_query::<T>();
+        let query = dialect::create_table_query::<T>(self.dialect(), self.schema::<T>());
         match self.exec(query.as_slice()) {
             Ok(_) => (),
             Err(_) => fail!("{}", self.get_errmsg())
         }
+        for query in dialect::create_index_queries::<T>(self.dialect(), self.schema::<T>()).iter() {
+            self.execute(query.as_slice());
+        }
     }
 
-    fn insert_many<'r, T:Table, Iter: Iterator<&'r T>>(&self, records: Iter) {
-        match self.prepare(super::insert_query::<T>(), &None) {
-            Err(_) => fail!("{}", self.get_errmsg()),
-            Ok(cursor) => {
-                let mut iter = records;
-                for record in iter {
-                    record.bind(&cursor);
-                    cursor.step();
-                    cursor.reset();
+    fn insert_many<'r, T:Table, Iter: Iterator<&'r T>>(&self, records: Iter) -> Result<(), SqlError> {
+        let mut iter = records;
+        with_savepoint(self, false, || {
+            match self.prepare(dialect::insert_query::<T>(self.dialect(), self.schema::<T>()).as_slice(), &None) {
+                Err(_) => Err(SqlFailure(self.get_errmsg())),
+                Ok(cursor) => {
+                    for record in iter.by_ref() {
+                        record.bind(&cursor);
+                        let result = cursor.step();
+                        cursor.reset();
+                        match result {
+                            sqlite3::SQLITE_DONE => (),
+                            sqlite3::SQLITE_CONSTRAINT =>
+                                return Err(constraint_error(super::table_name::<T>(), self.get_errmsg().as_slice())),
+                            _ => return Err(SqlFailure(self.get_errmsg()))
+                        }
+                    }
+                    Ok(())
                 }
             }
-        }
+        })
     }
 
-    unsafe fn select_table<'r, T>(&'r self, query: &str) -> SqlTableIter<'r, T> {
+    unsafe fn select_table<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlTableIter<'r, T> {
         match self.prepare(query, &None) {
             Err(_) => fail!("{}", self.get_errmsg()),
-            Ok(cursor) => SqlTableIter {
-                db: self,
-                cursor: box cursor
+            Ok(cursor) => {
+                bind_params(&cursor, params);
+                SqlTableIter {
+                    db: self,
+                    cursor: box cursor
+                }
             }
         }
     }
 
-    unsafe fn select_columns<'r, T>(&'r self, query: &str) -> SqlSelectIter<'r, T> {
+    unsafe fn select_columns<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlSelectIter<'r, T> {
         match self.prepare(query, &None) {
             Err(_) => fail!("{}", self.get_errmsg()),
-            Ok(cursor) => SqlSelectIter {
-                db: self,
-                cursor: box cursor
+            Ok(cursor) => {
+                bind_params(&cursor, params);
+                SqlSelectIter {
+                    db: self,
+                    cursor: box cursor
+                }
             }
         }
     }
@@ -0,0 +1,94 @@
+use std::ascii::StrAsciiExt;
+
+use adapter::{SqlAdapter, SqlSelectIter, ColumnInfo};
+
+// The Rust type of a column with the given declared type, following SQLite's
+// type affinity rules and the names in `SqlPrimitive::prim_typename`.
+// `None` for BLOB and untyped columns, which have no `SqlPrimitive`, and for
+// NUMERIC ones such as DATE or DECIMAL, whose values need not be floats.
+pub fn rust_type(decltype: &str) -> Option<&'static str> {
+    let decltype = decltype.to_ascii_upper();
+    let decltype = decltype.as_slice();
+    if decltype.contains("INT") {
+        Some("int")
+    } else if decltype.contains("CHAR") || decltype.contains("CLOB") || decltype.contains("TEXT") {
+        Some("String")
+    } else if decltype.contains("REAL") || decltype.contains("FLOA") || decltype.contains("DOUB") {
+        Some("f64")
+    } else {
+        None
+    }
+}
+
+static KEYWORDS: &'static [&'static str] = &[
+    "alignof", "as", "be", "box", "break", "const", "continue", "crate", "do", "else", "enum",
+    "extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+    "mut", "offsetof", "once", "override", "priv", "proc", "pub", "pure", "ref", "return",
+    "self", "sizeof", "static", "struct", "super", "trait", "true", "type", "typeof", "unsafe",
+    "unsized", "use", "virtual", "while", "yield"
+];
+
+// Whether `name` can be used as is for a struct or field, which `#[sql_table]`
+// names its table and columns after.
+pub fn is_rust_ident(name: &str) -> bool {
+    let valid = match name.chars().next() {
+        Some(c) => (c.is_alphabetic() || c == '_') && name.chars().all(|c| c.is_alphanumeric() || c == '_'),
+        None => false
+    };
+    valid && !KEYWORDS.iter().any(|keyword| *keyword == name)
+}
+
+pub fn table_names<A: SqlAdapter>(db: &A) -> Vec<String> {
+    let query = "SELECT name FROM sqlite_master \
+                 WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name;";
+    let rows: SqlSelectIter<(String,)> = unsafe { db.select_columns(query, []) };
+    rows.map(|(name,)| name).collect()
+}
+
+// Renders a `#[sql_table]` struct for the columns of a live table.
+// `get_row` reads the columns by position, so a table with any column that
+// has no Rust type or name is refused as a whole, listing those columns.
+pub fn table_struct(table: &str, columns: &[ColumnInfo]) -> Result<String, String> {
+    let mut problems = Vec::new();
+    if !is_rust_ident(table) {
+        problems.push(format!("table name `{}` is not a Rust identifier", table));
+    }
+    for column in columns.iter() {
+        if !is_rust_ident(column.name.as_slice()) {
+            problems.push(format!("column name `{}` is not a Rust identifier", column.name));
+        }
+        if rust_type(column.typename.as_slice()).is_none() {
+            problems.push(format!("column `{}` has unsupported type `{}`", column.name, column.typename));
+        }
+    }
+    if !problems.is_empty() {
+        return Err(format!("cannot generate {}: {}", table, problems.connect(", ")));
+    }
+
+    let mut out = String::new();
+    out.push_str(format!("#[sql_table]\npub struct {} \\{\n", table).as_slice());
+
+    let primary_keys = columns.iter().filter(|column| column.primary_key).count();
+    for (i, column) in columns.iter().enumerate() {
+        let sep = if i + 1 == columns.len() { "" } else { "," };
+        let ty = rust_type(column.typename.as_slice()).unwrap();
+        if column.primary_key && primary_keys == 1 {
+            out.push_str("    #[sql_primary_key]\n");
+        }
+        match column.default {
+            Some(ref default) => {
+                let escaped = default.replace("\\", "\\\\").replace("\"", "\\\"");
+                out.push_str(format!("    #[sql_default = \"{}\"]\n", escaped).as_slice());
+            },
+            None => ()
+        }
+        if column.notnull {
+            out.push_str(format!("    pub {}: {}{}\n", column.name, ty, sep).as_slice());
+        } else {
+            out.push_str(format!("    pub {}: Option<{}>{}\n", column.name, ty, sep).as_slice());
+        }
+    }
+
+    out.push_str("}\n");
+    Ok(out)
+}
@@ -0,0 +1,182 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::intrinsics::TypeId;
+
+use super::{Table, Column, UniqueConstraint, CheckConstraint};
+
+// How an adapter spells the parts of the queries rendered from `Table`
+// metadata. Type names are the ones `SqlPrimitive` declares.
+pub trait SqlDialect {
+    fn name(&self) -> &'static str;
+    fn typename(&self, typename: &str) -> String;
+    fn quote_identifier(&self, name: &str) -> String;
+    // `idx` counts from 1.
+    fn placeholder(&self, idx: uint) -> String;
+
+    // The type of a column which is part of a key or an index.
+    fn key_typename(&self, typename: &str) -> String {
+        self.typename(typename)
+    }
+
+    // Whether `ALTER TABLE ... ADD COLUMN` accepts `default`, which fills the
+    // rows the table already has.
+    fn added_column_default(&self, _: &str) -> bool {
+        true
+    }
+
+    // The part of `CREATE INDEX` naming the index and its table.
+    fn index_on(&self, schema: Option<&str>, index: &str, table: &str) -> String {
+        let table = match schema {
+            Some(schema) => format!("{}.{}", self.quote_identifier(schema), self.quote_identifier(table)),
+            None => self.quote_identifier(table)
+        };
+        format!("{} ON {}", self.quote_identifier(index), table)
+    }
+
+    // `on` is rendered by `index_on` and `columns` quoted already.
+    fn create_index_query(&self, on: &str, columns: &str, predicate: Option<&str>) -> String {
+        match predicate {
+            Some(predicate) => format!("CREATE INDEX IF NOT EXISTS {} ({}) WHERE {};", on, columns, predicate),
+            None => format!("CREATE INDEX IF NOT EXISTS {} ({});", on, columns)
+        }
+    }
+}
+
+// The queries of each table are rendered once per dialect, schema and task.
+// `schema` is where the connection binds the table, as `SqlAdapter::schema`
+// tells.
+local_data_key!(query_cache: RefCell<HashMap<(&'static str, &'static str, TypeId, String), String>>)
+
+fn cached<T: Table>(dialect: &SqlDialect, schema: &Option<String>, kind: &'static str,
+                    render: || -> String) -> String {
+    let table = super::qualified_name(schema.as_ref().map(|schema| schema.as_slice()), super::table_name::<T>());
+    let key = (dialect.name(), kind, super::type_key::<T>(), table);
+    if query_cache.get().is_none() {
+        query_cache.replace(Some(RefCell::new(HashMap::new())));
+    }
+    let found = query_cache.get().unwrap().borrow().find(&key).map(|query| query.clone());
+    match found {
+        Some(query) => query,
+        None => {
+            let query = render();
+            query_cache.get().unwrap().borrow_mut().insert(key, query.clone());
+            query
+        }
+    }
+}
+
+fn quote_list(dialect: &SqlDialect, names: &[&'static str]) -> String {
+    let quoted: Vec<String> = names.iter().map(|name| dialect.quote_identifier(*name)).collect();
+    quoted.connect(", ")
+}
+
+pub fn qualified_table<T: Table>(dialect: &SqlDialect, schema: Option<String>) -> String {
+    let table = dialect.quote_identifier(super::table_name::<T>());
+    match schema {
+        Some(schema) => format!("{}.{}", dialect.quote_identifier(schema.as_slice()), table),
+        None => table
+    }
+}
+
+// `keyed` columns are part of a key or an index of the table.
+pub fn column_definition(dialect: &SqlDialect, table: &str, column: &Column, keyed: bool) -> String {
+    let typename = if keyed || column.primary_key || column.unique {
+        dialect.key_typename(column.typename.as_slice())
+    } else {
+        dialect.typename(column.typename.as_slice())
+    };
+    let mut def = format!("{} {}", dialect.quote_identifier(column.name), typename);
+    if !column.nullable {
+        def.push_str(" not null");
+    }
+    match column.default {
+        Some(default) => def.push_str(format!(" DEFAULT {}", default).as_slice()),
+        None => ()
+    }
+    for (idx, check) in column.checks.iter().enumerate() {
+        let name = match idx {
+            0 => format!("{}_{}_check", table, column.name),
+            _ => format!("{}_{}_check_{}", table, column.name, idx + 1)
+        };
+        def.push_str(format!(" CONSTRAINT {} CHECK ({})", dialect.quote_identifier(name.as_slice()),
+                             *check).as_slice());
+    }
+    if column.primary_key {
+        def.push_str(" PRIMARY KEY");
+    }
+    if column.unique {
+        def.push_str(" UNIQUE");
+    }
+    match column.references {
+        Some(ref reference) => {
+            def.push_str(format!(" REFERENCES {}({})",
+                                 dialect.quote_identifier(reference.table.as_slice()),
+                                 dialect.quote_identifier(reference.column.as_slice())).as_slice());
+            match reference.on_delete {
+                Some(action) => def.push_str(format!(" ON DELETE {}", action).as_slice()),
+                None => ()
+            }
+        },
+        None => ()
+    }
+    def
+}
+
+pub fn create_table_query<T: Table>(dialect: &SqlDialect, schema: Option<String>) -> String {
+    let table = super::table_name::<T>();
+    cached::<T>(dialect, &schema, "create", || {
+        let constraints = super::constraints::<T>();
+        let indexes = super::indexes::<T>();
+        let mut defs: Vec<String> = super::columns::<T>().iter().map(|column| {
+            let keyed = indexes.iter().any(|index| index.columns.contains(&column.name)) ||
+                constraints.iter().any(|constraint| match *constraint {
+                    UniqueConstraint(ref columns) => columns.contains(&column.name),
+                    CheckConstraint(..) => false
+                });
+            column_definition(dialect, table, column, keyed)
+        }).collect();
+        for constraint in constraints.iter() {
+            defs.push(match *constraint {
+                UniqueConstraint(ref columns) =>
+                    format!("UNIQUE ({})", quote_list(dialect, columns.as_slice())),
+                CheckConstraint(name, check) =>
+                    format!("CONSTRAINT {} CHECK ({})", dialect.quote_identifier(name), check)
+            });
+        }
+        format!("CREATE TABLE IF NOT EXISTS {} ({});", qualified_table::<T>(dialect, schema.clone()),
+                defs.connect(", "))
+    })
+}
+
+pub fn create_index_queries<T: Table>(dialect: &SqlDialect, schema: Option<String>) -> Vec<String> {
+    let table = super::table_name::<T>();
+    super::indexes::<T>().iter().map(|index| {
+        let on = dialect.index_on(schema.as_ref().map(|schema| schema.as_slice()), index.name, table);
+        dialect.create_index_query(on.as_slice(), quote_list(dialect, index.columns.as_slice()).as_slice(),
+                                   index.predicate)
+    }).collect()
+}
+
+pub fn insert_query<T: Table>(dialect: &SqlDialect, schema: Option<String>) -> String {
+    cached::<T>(dialect, &schema, "insert", || {
+        let columns = super::columns::<T>();
+        let names: Vec<&'static str> = columns.iter().map(|column| column.name).collect();
+        let values: Vec<String> = columns.iter().enumerate().map(|(idx, column)| {
+            let placeholder = dialect.placeholder(idx + 1);
+            // A `None` bound to a defaulted column stands for the default,
+            // so it is stored as the default and never as NULL.
+            match column.default {
+                Some(default) if column.nullable => format!("COALESCE({}, {})", placeholder, default),
+                _ => placeholder
+            }
+        }).collect();
+        format!("INSERT INTO {} ({}) VALUES ({});", qualified_table::<T>(dialect, schema.clone()),
+                quote_list(dialect, names.as_slice()), values.connect(", "))
+    })
+}
+
+pub fn select_query<T: Table>(dialect: &SqlDialect, schema: Option<String>) -> String {
+    cached::<T>(dialect, &schema, "select", || {
+        format!("SELECT * FROM {};", qualified_table::<T>(dialect, schema.clone()))
+    })
+}
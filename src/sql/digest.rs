@@ -0,0 +1,234 @@
+// Message digests needed by the password authentication of database servers.
+
+static SHA256_TABLE: [u32, ..64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
+];
+
+static MD5_SHIFTS: [u32, ..64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+    5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+    4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+    6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21
+];
+
+static MD5_TABLE: [u32, ..64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee,
+    0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be,
+    0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa,
+    0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed,
+    0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c,
+    0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05,
+    0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039,
+    0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1,
+    0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391
+];
+
+fn rotate_left(x: u32, n: u32) -> u32 {
+    (x << n) | (x >> (32 - n))
+}
+
+fn rotate_right(x: u32, n: u32) -> u32 {
+    (x >> n) | (x << (32 - n))
+}
+
+fn big_endian_words(chunk: &[u8], words: &mut [u32]) {
+    for i in range(0u, 16) {
+        words[i] = (chunk[4 * i] as u32 << 24) |
+                   (chunk[4 * i + 1] as u32 << 16) |
+                   (chunk[4 * i + 2] as u32 << 8) |
+                   (chunk[4 * i + 3] as u32);
+    }
+}
+
+fn big_endian_digest(state: &[u32]) -> Vec<u8> {
+    let mut digest = Vec::with_capacity(state.len() * 4);
+    for word in state.iter() {
+        for i in range(0u, 4) {
+            digest.push((*word >> (8 * (3 - i))) as u8);
+        }
+    }
+    digest
+}
+
+// Appends the padding and the bit length shared by MD5, SHA-1 and SHA-256.
+fn pad_message(input: &[u8], big_endian: bool) -> Vec<u8> {
+    let bit_len = (input.len() as u64) * 8;
+    let mut msg = Vec::from_slice(input);
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    for i in range(0u, 8) {
+        let shift = if big_endian { 8 * (7 - i) } else { 8 * i };
+        msg.push((bit_len >> shift) as u8);
+    }
+    msg
+}
+
+pub fn md5(input: &[u8]) -> Vec<u8> {
+    let mut state = [0x67452301u32, 0xefcdab89, 0x98badcfe, 0x10325476];
+    let msg = pad_message(input, false);
+
+    for chunk in msg.as_slice().chunks(64) {
+        let mut words = [0u32, ..16];
+        for i in range(0u, 16) {
+            words[i] = (chunk[4 * i] as u32) |
+                       (chunk[4 * i + 1] as u32 << 8) |
+                       (chunk[4 * i + 2] as u32 << 16) |
+                       (chunk[4 * i + 3] as u32 << 24);
+        }
+
+        let (mut a, mut b, mut c, mut d) = (state[0], state[1], state[2], state[3]);
+        for i in range(0u, 64) {
+            let (f, g) = if i < 16 {
+                ((b & c) | (!b & d), i)
+            } else if i < 32 {
+                ((d & b) | (!d & c), (5 * i + 1) % 16)
+            } else if i < 48 {
+                (b ^ c ^ d, (3 * i + 5) % 16)
+            } else {
+                (c ^ (b | !d), (7 * i) % 16)
+            };
+            let rotated = rotate_left(a + f + MD5_TABLE[i] + words[g], MD5_SHIFTS[i]);
+            a = d;
+            d = c;
+            c = b;
+            b = b + rotated;
+        }
+
+        state[0] += a;
+        state[1] += b;
+        state[2] += c;
+        state[3] += d;
+    }
+
+    let mut digest = Vec::with_capacity(16);
+    for word in state.iter() {
+        for i in range(0u, 4) {
+            digest.push((*word >> (8 * i)) as u8);
+        }
+    }
+    digest
+}
+
+pub fn to_hex(digest: &[u8]) -> String {
+    let mut hex = String::with_capacity(digest.len() * 2);
+    for byte in digest.iter() {
+        hex.push_str(format!("{:02x}", *byte).as_slice());
+    }
+    hex
+}
+
+pub fn sha1(input: &[u8]) -> Vec<u8> {
+    let mut state = [0x67452301u32, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let msg = pad_message(input, true);
+
+    for chunk in msg.as_slice().chunks(64) {
+        let mut words = [0u32, ..80];
+        big_endian_words(chunk, words.as_mut_slice());
+        for i in range(16u, 80) {
+            words[i] = rotate_left(words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16], 1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (state[0], state[1], state[2], state[3], state[4]);
+        for i in range(0u, 80) {
+            let (f, k) = if i < 20 {
+                ((b & c) | (!b & d), 0x5a827999)
+            } else if i < 40 {
+                (b ^ c ^ d, 0x6ed9eba1)
+            } else if i < 60 {
+                ((b & c) | (b & d) | (c & d), 0x8f1bbcdc)
+            } else {
+                (b ^ c ^ d, 0xca62c1d6)
+            };
+            let temp = rotate_left(a, 5) + f + e + k + words[i];
+            e = d;
+            d = c;
+            c = rotate_left(b, 30);
+            b = a;
+            a = temp;
+        }
+
+        state[0] += a;
+        state[1] += b;
+        state[2] += c;
+        state[3] += d;
+        state[4] += e;
+    }
+
+    big_endian_digest(state.as_slice())
+}
+
+pub fn sha256(input: &[u8]) -> Vec<u8> {
+    let mut state = [0x6a09e667u32, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+                     0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+    let msg = pad_message(input, true);
+
+    for chunk in msg.as_slice().chunks(64) {
+        let mut words = [0u32, ..64];
+        big_endian_words(chunk, words.as_mut_slice());
+        for i in range(16u, 64) {
+            let s0 = rotate_right(words[i - 15], 7) ^ rotate_right(words[i - 15], 18) ^ (words[i - 15] >> 3);
+            let s1 = rotate_right(words[i - 2], 17) ^ rotate_right(words[i - 2], 19) ^ (words[i - 2] >> 10);
+            words[i] = words[i - 16] + s0 + words[i - 7] + s1;
+        }
+
+        let mut v = state;
+        for i in range(0u, 64) {
+            let s1 = rotate_right(v[4], 6) ^ rotate_right(v[4], 11) ^ rotate_right(v[4], 25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let temp1 = v[7] + s1 + ch + SHA256_TABLE[i] + words[i];
+            let s0 = rotate_right(v[0], 2) ^ rotate_right(v[0], 13) ^ rotate_right(v[0], 22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let temp2 = s0 + maj;
+            v = [temp1 + temp2, v[0], v[1], v[2], v[3] + temp1, v[4], v[5], v[6]];
+        }
+
+        for i in range(0u, 8) {
+            state[i] += v[i];
+        }
+    }
+
+    big_endian_digest(state.as_slice())
+}
+
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block = if key.len() > 64 { sha256(key) } else { Vec::from_slice(key) };
+    let len = block.len();
+    block.grow(64 - len, &0u8);
+    let mut inner: Vec<u8> = block.iter().map(|byte| *byte ^ 0x36).collect();
+    inner.push_all(message);
+    let mut outer: Vec<u8> = block.iter().map(|byte| *byte ^ 0x5c).collect();
+    outer.push_all(sha256(inner.as_slice()).as_slice());
+    sha256(outer.as_slice())
+}
+
+// PBKDF2 with HMAC-SHA-256, for the single block of output the `Hi` function
+// of SCRAM-SHA-256 takes.
+pub fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: uint) -> Vec<u8> {
+    let mut first = Vec::from_slice(salt);
+    first.push_all([0u8, 0, 0, 1]);
+    let mut block = hmac_sha256(password, first.as_slice());
+    let mut result = block.clone();
+    for _ in range(1, iterations) {
+        block = hmac_sha256(password, block.as_slice());
+        for (byte, other) in result.mut_iter().zip(block.iter()) {
+            *byte ^= *other;
+        }
+    }
+    result
+}
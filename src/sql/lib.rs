@@ -4,19 +4,39 @@
 #![license = "MIT"]
 #![crate_type = "dylib"]
 
-#![feature(macro_rules)]
+#![feature(macro_rules, struct_variant, unsafe_destructor)]
 
 extern crate debug;
+extern crate num;
+extern crate serialize;
 extern crate sqlite3;
+extern crate sync;
+extern crate time;
+
+use std::intrinsics::{type_id, TypeId};
 
 pub mod adapter;
+pub mod codegen;
+pub mod dialect;
+pub mod digest;
+pub mod migrate;
+pub mod mock;
+pub mod mysql;
+pub mod pool;
+pub mod postgres;
+pub mod query;
 pub mod selector;
+pub mod shared;
+pub mod sqlite;
+pub mod trace;
 
 pub trait Table {
     fn table_name(_: Option<&Self>) -> &str;
-    fn create_table_query(_: Option<&Self>) -> String;
-    fn insert_query(_: Option<&Self>) -> &str;
-    fn select_query(_: Option<&Self>) -> &str;
+    // The attached database set by `#[sql_schema]`, if any.
+    fn schema(_: Option<&Self>) -> Option<&'static str>;
+    fn columns(_: Option<&Self>) -> Vec<Column>;
+    fn constraints(_: Option<&Self>) -> Vec<Constraint>;
+    fn indexes(_: Option<&Self>) -> Vec<Index>;
     fn bind(&self, cursor: &adapter::SqlAdapterCursor);
     fn get_row(cursor: &adapter::SqlAdapterCursor) -> Self;
 }
@@ -25,22 +45,165 @@ pub fn table_name<T: Table>() -> &str {
     Table::table_name(None::<&T>)
 }
 
+// Identifies the type `T` itself, whatever table name it declares.
+pub fn type_key<T>() -> TypeId {
+    unsafe { type_id::<T>() }
+}
+
+// The `#[sql_schema]` of `T`. Connections may bind its table elsewhere, which
+// `SqlAdapter::schema` tells.
+pub fn schema<T: Table>() -> Option<String> {
+    Table::schema(None::<&T>).map(|schema| schema.to_string())
+}
+
+pub fn qualified_name(schema: Option<&str>, table: &str) -> String {
+    match schema {
+        Some(schema) => format!("{}.{}", schema, table),
+        None => table.to_string()
+    }
+}
+
+// The table name as queries refer to it, as in `archive.Post`.
+pub fn qualified_table_name<T: Table>() -> String {
+    qualified_name(Table::schema(None::<&T>), table_name::<T>())
+}
+
+pub fn columns<T: Table>() -> Vec<Column> {
+    Table::columns(None::<&T>)
+}
+
+pub fn constraints<T: Table>() -> Vec<Constraint> {
+    Table::constraints(None::<&T>)
+}
+
+pub fn indexes<T: Table>() -> Vec<Index> {
+    Table::indexes(None::<&T>)
+}
+
+// A list of tables given as a tuple type, as in `db.create_tables::<(A, B)>()`.
+pub trait TableList {
+    fn create_tables<D: adapter::SqlAdapter>(_: Option<&Self>, db: &D);
+}
+
+macro_rules! impl_table_list(
+    ($($table:ident),+) => (
+        impl<$($table: Table),+> TableList for ($($table,)+) {
+            fn create_tables<D: adapter::SqlAdapter>(_: Option<&($($table,)+)>, db: &D) {
+                $(db.create_table_if_not_exists::<$table>();)+
+            }
+        }
+    )
+)
+
+impl_table_list!(T1)
+impl_table_list!(T1, T2)
+impl_table_list!(T1, T2, T3)
+impl_table_list!(T1, T2, T3, T4)
+impl_table_list!(T1, T2, T3, T4, T5)
+impl_table_list!(T1, T2, T3, T4, T5, T6)
+
+// The queries below are rendered for SQLite. Other adapters go through
+// `dialect` with their own `SqlDialect`.
+
 pub fn create_table_query<T: Table>() -> String {
-    Table::create_table_query(None::<&T>)
+    dialect::create_table_query::<T>(&sqlite::SqliteDialect, schema::<T>())
+}
+
+pub fn create_index_queries<T: Table>() -> Vec<String> {
+    dialect::create_index_queries::<T>(&sqlite::SqliteDialect, schema::<T>())
+}
+
+pub fn insert_query<T: Table>() -> String {
+    dialect::insert_query::<T>(&sqlite::SqliteDialect, schema::<T>())
+}
+
+pub fn select_query<T: Table>() -> String {
+    dialect::select_query::<T>(&sqlite::SqliteDialect, schema::<T>())
+}
+
+// Implemented by `#[sql_table]` for tables with a `#[sql_primary_key]` field
+// of type `K`.
+pub trait PrimaryKey<K> {
+    fn primary_key_name(_: Option<&Self>) -> &str;
+}
+
+// Checks that a `#[sql_references(T)]` field of type `F` can hold the
+// primary key of `T`. Only its signature matters.
+pub fn check_reference<F: SqlComparable<K>, K, T: PrimaryKey<K>>(_: Option<&F>, _: Option<&T>) {}
+
+#[deriving(Clone, PartialEq, Show)]
+pub struct Column {
+    pub name: &'static str,
+    pub typename: String,
+    pub nullable: bool,
+    pub default: Option<&'static str>,
+    pub checks: Vec<&'static str>,
+    pub primary_key: bool,
+    pub unique: bool,
+    pub references: Option<Reference>
+}
+
+pub fn column<T: SqlType>(name: &'static str) -> Column {
+    Column {
+        name: name,
+        typename: SqlType::base_typename(None::<&T>),
+        nullable: SqlType::nullable(None::<&T>),
+        default: None,
+        checks: Vec::new(),
+        primary_key: false,
+        unique: false,
+        references: None
+    }
+}
+
+// The primary key a `#[sql_references]` column points to. SQLite only
+// enforces it on connections of `sqlite::open`, and MySQL not at all.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Reference {
+    pub table: String,
+    pub column: String,
+    pub on_delete: Option<&'static str>
+}
+
+// Constraints declared on the struct, spanning several columns.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Constraint {
+    UniqueConstraint(Vec<&'static str>),
+    // Constraint name, expression.
+    CheckConstraint(&'static str, &'static str)
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub struct Index {
+    pub name: &'static str,
+    pub columns: Vec<&'static str>,
+    pub predicate: Option<&'static str>
 }
 
-pub fn insert_query<T: Table>() -> &str {
-    Table::insert_query(None::<&T>)
+#[deriving(Clone, PartialEq, Show)]
+pub enum SqlValue {
+    NullValue,
+    IntValue(int),
+    TextValue(String),
+    FloatValue(f64)
 }
 
-pub fn select_query<T: Table>() -> &str {
-    Table::select_query(None::<&T>)
+impl SqlValue {
+    pub fn bind(&self, cursor: &adapter::SqlAdapterCursor, idx: int) {
+        match *self {
+            NullValue => cursor.bind_null(idx),
+            IntValue(v) => cursor.bind_int(idx, v),
+            TextValue(ref v) => cursor.bind_str(idx, v.as_slice()),
+            FloatValue(v) => cursor.bind_f64(idx, v)
+        }
+    }
 }
 
 pub trait SqlPrimitive {
     fn prim_typename(_: Option<&Self>) -> &str;
     fn prim_bind(&self, cursor: &adapter::SqlAdapterCursor, idx: int);
     fn prim_get(cursor: &adapter::SqlAdapterCursor, idx: int) -> Self;
+    fn prim_value(&self) -> SqlValue;
 }
 
 pub fn prim_typename<T: SqlPrimitive>() -> &str {
@@ -59,6 +222,10 @@ impl SqlPrimitive for int {
     fn prim_get(cursor: &adapter::SqlAdapterCursor, idx: int) -> int {
         cursor.get_prim_int(idx)
     }
+
+    fn prim_value(&self) -> SqlValue {
+        IntValue(*self)
+    }
 }
 
 impl SqlPrimitive for String {
@@ -73,6 +240,10 @@ impl SqlPrimitive for String {
     fn prim_get(cursor: &adapter::SqlAdapterCursor, idx: int) -> String {
         cursor.get_prim_str(idx)
     }
+
+    fn prim_value(&self) -> SqlValue {
+        TextValue(self.clone())
+    }
 }
 
 impl SqlPrimitive for f64 {
@@ -87,12 +258,19 @@ impl SqlPrimitive for f64 {
     fn prim_get(cursor: &adapter::SqlAdapterCursor, idx: int) -> f64 {
         cursor.get_prim_f64(idx)
     }
+
+    fn prim_value(&self) -> SqlValue {
+        FloatValue(*self)
+    }
 }
 
 pub trait SqlType {
     fn typename(_: Option<&Self>) -> String;
+    fn base_typename(_: Option<&Self>) -> String;
+    fn nullable(_: Option<&Self>) -> bool;
     fn bind(&self, cursor: &adapter::SqlAdapterCursor, idx: int);
     fn get_col(cursor: &adapter::SqlAdapterCursor, idx: int) -> Self;
+    fn value(&self) -> SqlValue;
 }
 
 pub fn sql_typename<T: SqlType>() -> String {
@@ -104,6 +282,14 @@ impl<T:SqlPrimitive> SqlType for Option<T> {
         prim_typename::<T>().to_str()
     }
 
+    fn base_typename(_: Option<&Option<T>>) -> String {
+        prim_typename::<T>().to_str()
+    }
+
+    fn nullable(_: Option<&Option<T>>) -> bool {
+        true
+    }
+
     fn bind(&self, cursor: &adapter::SqlAdapterCursor, idx: int) {
         match self {
             &None => cursor.bind_null(idx),
@@ -118,6 +304,13 @@ impl<T:SqlPrimitive> SqlType for Option<T> {
             Some(SqlPrimitive::prim_get(cursor, idx))
         }
     }
+
+    fn value(&self) -> SqlValue {
+        match self {
+            &None => NullValue,
+            &Some(ref prim) => prim.prim_value()
+        }
+    }
 }
 
 pub fn bind_sqltype<T: SqlType>(value: &T, cursor: &adapter::SqlAdapterCursor, idx: int) {
@@ -131,6 +324,14 @@ macro_rules! impl_sqltype(
                 format!("{} not null", prim_typename::<$prim_ty>())
             }
 
+            fn base_typename(_: Option<&$prim_ty>) -> String {
+                prim_typename::<$prim_ty>().to_str()
+            }
+
+            fn nullable(_: Option<&$prim_ty>) -> bool {
+                false
+            }
+
             fn bind(&self, cursor: &adapter::SqlAdapterCursor, idx: int) {
                 self.prim_bind(cursor, idx)
             }
@@ -138,6 +339,10 @@ macro_rules! impl_sqltype(
             fn get_col(cursor: &adapter::SqlAdapterCursor, idx: int) -> $prim_ty {
                 SqlPrimitive::prim_get(cursor, idx)
             }
+
+            fn value(&self) -> SqlValue {
+                self.prim_value()
+            }
         }
     )
 )
@@ -145,3 +350,61 @@ macro_rules! impl_sqltype(
 impl_sqltype!(int)
 impl_sqltype!(String)
 impl_sqltype!(f64)
+
+// Values of type `Self` may be compared against a column of type `T` in a
+// `sql!` WHERE clause. Nullability is ignored, the primitive types must match.
+pub trait SqlComparable<T> {}
+
+macro_rules! impl_comparable(
+    ($prim_ty:ty) => (
+        impl SqlComparable<$prim_ty> for $prim_ty {}
+        impl SqlComparable<Option<$prim_ty>> for $prim_ty {}
+        impl SqlComparable<$prim_ty> for Option<$prim_ty> {}
+        impl SqlComparable<Option<$prim_ty>> for Option<$prim_ty> {}
+    )
+)
+
+impl_comparable!(int)
+impl_comparable!(String)
+impl_comparable!(f64)
+
+// `Self op R` in a select list yields a column of type `O`, where `op` is
+// `+`, `-` or `*` for `SqlArith` and `||` for `SqlConcat`.
+// The result is nullable if either operand is.
+pub trait SqlArith<R, O> {}
+
+pub trait SqlConcat<R, O> {}
+
+macro_rules! impl_binary_op(
+    ($op:ident, $prim_ty:ty) => (
+        impl $op<$prim_ty, $prim_ty> for $prim_ty {}
+        impl $op<Option<$prim_ty>, Option<$prim_ty>> for $prim_ty {}
+        impl $op<$prim_ty, Option<$prim_ty>> for Option<$prim_ty> {}
+        impl $op<Option<$prim_ty>, Option<$prim_ty>> for Option<$prim_ty> {}
+    )
+)
+
+impl_binary_op!(SqlArith, int)
+impl_binary_op!(SqlArith, f64)
+impl_binary_op!(SqlConcat, String)
+
+// `/` and `%` yield NULL when dividing by zero, so their result is always
+// nullable.
+pub trait SqlDivide<R, O> {}
+
+macro_rules! impl_divide(
+    ($prim_ty:ty) => (
+        impl SqlDivide<$prim_ty, Option<$prim_ty>> for $prim_ty {}
+        impl SqlDivide<Option<$prim_ty>, Option<$prim_ty>> for $prim_ty {}
+        impl SqlDivide<$prim_ty, Option<$prim_ty>> for Option<$prim_ty> {}
+        impl SqlDivide<Option<$prim_ty>, Option<$prim_ty>> for Option<$prim_ty> {}
+    )
+)
+
+impl_divide!(int)
+impl_divide!(f64)
+
+// Non-null text columns and values, the only ones `like` and `glob` apply to.
+pub trait SqlText {}
+
+impl SqlText for String {}
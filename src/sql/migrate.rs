@@ -0,0 +1,334 @@
+use std::ascii::StrAsciiExt;
+use std::fmt;
+
+use adapter::{SqlAdapter, ColumnInfo};
+use dialect;
+use dialect::SqlDialect;
+use digest;
+use super::{Table, Column, SqlType, TextValue};
+use time;
+
+#[deriving(Clone, PartialEq)]
+pub enum SchemaProblem {
+    // A column in the database is no longer in the struct.
+    DroppedColumn(String),
+    // Column name, type in the struct, type in the database.
+    ChangedType(String, String, String),
+    // Column name, whether the struct allows NULL.
+    ChangedNullability(String, bool),
+    // A new column which is neither nullable nor defaulted.
+    NotNullColumn(String),
+    // A new column which is not nullable, with a default the database cannot
+    // add to the rows the table already has.
+    NotNullDefaultColumn(String),
+    // A column of the struct which is not in the database.
+    MissingColumn(String),
+    // Column name, position in the struct, position in the database.
+    MovedColumn(String, uint, uint)
+}
+
+impl fmt::Show for SchemaProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DroppedColumn(ref name) =>
+                write!(f, "column `{}` exists in the database but not in the struct", name),
+            ChangedType(ref name, ref expected, ref found) =>
+                write!(f, "column `{}` has type `{}` in the struct but `{}` in the database",
+                       name, expected, found),
+            ChangedNullability(ref name, true) =>
+                write!(f, "column `{}` is nullable in the struct but not null in the database", name),
+            ChangedNullability(ref name, false) =>
+                write!(f, "column `{}` is not null in the struct but nullable in the database", name),
+            NotNullColumn(ref name) =>
+                write!(f, "new column `{}` is not null and has no default", name),
+            NotNullDefaultColumn(ref name) =>
+                write!(f, "new column `{}` is not null and its default cannot be added to existing rows", name),
+            MissingColumn(ref name) =>
+                write!(f, "column `{}` exists in the struct but not in the database", name),
+            MovedColumn(ref name, expected, found) =>
+                write!(f, "column `{}` is at position {} in the struct but {} in the database",
+                       name, expected, found)
+        }
+    }
+}
+
+// Changes to a table which `migrate` refused to apply.
+#[deriving(Clone, PartialEq)]
+pub struct MigrationError {
+    pub table: String,
+    pub problems: Vec<SchemaProblem>
+}
+
+impl fmt::Show for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "cannot migrate table `{}`:", self.table));
+        for problem in self.problems.iter() {
+            try!(write!(f, "\n  {}", problem));
+        }
+        Ok(())
+    }
+}
+
+// The queries `migrate` ran on a table, and the new columns it added without
+// their default, as the database could not add them with it. Their existing
+// rows were given the default, but rows inserted other than through the
+// struct are left NULL.
+#[deriving(Clone, PartialEq, Show)]
+pub struct MigrationReport {
+    pub queries: Vec<String>,
+    pub defaults_not_added: Vec<String>
+}
+
+// Compares the columns of a struct with the live table and lists the changes
+// `migrate` cannot make. Struct columns missing from the table are not listed.
+pub fn schema_problems(expected: &[Column], found: &[ColumnInfo]) -> Vec<SchemaProblem> {
+    let mut problems = Vec::new();
+
+    for (pos, info) in found.iter().enumerate() {
+        match expected.iter().position(|column| column.name == info.name.as_slice()) {
+            None => problems.push(DroppedColumn(info.name.clone())),
+            Some(expected_pos) => {
+                let column = &expected[expected_pos];
+                if expected_pos != pos {
+                    problems.push(MovedColumn(info.name.clone(), expected_pos, pos));
+                }
+                if !column.typename.as_slice().eq_ignore_ascii_case(info.typename.as_slice()) {
+                    problems.push(ChangedType(info.name.clone(), column.typename.clone(),
+                                              info.typename.clone()));
+                }
+                if column.nullable == info.notnull {
+                    problems.push(ChangedNullability(info.name.clone(), column.nullable));
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+// The table of `T` as `db` names it, in the schema it binds it to.
+fn bound_table_name<A: SqlAdapter, T: Table>(db: &A) -> String {
+    let schema = db.schema::<T>();
+    super::qualified_name(schema.as_ref().map(|schema| schema.as_slice()), super::table_name::<T>())
+}
+
+// Lists every difference between the columns of `T` and the live table which
+// would make `Table::get_row` misread rows, as `create_table_query` defines them.
+pub fn verify_table<A: SqlAdapter, T: Table>(db: &A) -> Vec<SchemaProblem> {
+    let expected = super::columns::<T>();
+    let found = db.table_columns(bound_table_name::<A, T>(db).as_slice());
+    let mut problems = schema_problems(expected.as_slice(), found.as_slice());
+
+    for column in expected.iter() {
+        if !found.iter().any(|info| info.name.as_slice() == column.name) {
+            problems.push(MissingColumn(column.name.to_string()));
+        }
+    }
+    problems
+}
+
+pub fn migrate_table<A: SqlAdapter, T: Table>(db: &A) -> Result<MigrationReport, MigrationError> {
+    let table = bound_table_name::<A, T>(db);
+    let found = db.table_columns(table.as_slice());
+
+    if found.is_empty() {
+        let mut queries = vec![dialect::create_table_query::<T>(db.dialect(), db.schema::<T>())];
+        queries.push_all_move(dialect::create_index_queries::<T>(db.dialect(), db.schema::<T>()));
+        for query in queries.iter() {
+            db.execute(query.as_slice());
+        }
+        return Ok(MigrationReport { queries: queries, defaults_not_added: Vec::new() })
+    }
+
+    let expected = super::columns::<T>();
+    let mut problems = schema_problems(expected.as_slice(), found.as_slice());
+    let mut queries = Vec::new();
+    let mut defaults_not_added = Vec::new();
+    let dialect = db.dialect();
+    let quoted_table = dialect::qualified_table::<T>(dialect, db.schema::<T>());
+
+    for column in expected.iter() {
+        if found.iter().any(|info| info.name.as_slice() == column.name) {
+            continue;
+        }
+        let not_null = if column.nullable { "" } else { " not null" };
+        let definition = format!("{} {}{}", dialect.quote_identifier(column.name),
+                                 dialect.typename(column.typename.as_slice()), not_null);
+        match column.default {
+            Some(default) if dialect.added_column_default(default) =>
+                queries.push(format!("ALTER TABLE {} ADD COLUMN {} DEFAULT {};", quoted_table, definition, default)),
+            // Inserts through the struct fill the default in themselves, and
+            // the existing rows are given it once added.
+            Some(default) if column.nullable => {
+                queries.push(format!("ALTER TABLE {} ADD COLUMN {};", quoted_table, definition));
+                queries.push(format!("UPDATE {} SET {} = {};", quoted_table,
+                                     dialect.quote_identifier(column.name), default));
+                defaults_not_added.push(column.name.to_string());
+            },
+            Some(_) => problems.push(NotNullDefaultColumn(column.name.to_string())),
+            None if column.nullable =>
+                queries.push(format!("ALTER TABLE {} ADD COLUMN {};", quoted_table, definition)),
+            None => problems.push(NotNullColumn(column.name.to_string()))
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(MigrationError {
+            table: table,
+            problems: problems
+        })
+    }
+
+    for query in queries.iter() {
+        db.execute(query.as_slice());
+    }
+    Ok(MigrationReport { queries: queries, defaults_not_added: defaults_not_added })
+}
+
+// A versioned migration applied by `Migrator`. SQL migrations are checksummed
+// so that editing one after it was applied is detected, functions are not.
+pub trait Migration<A> {
+    fn checksum(&self) -> Option<String>;
+    fn apply(&self, db: &A) -> Result<(), String>;
+}
+
+fn sql_checksum(sql: &str) -> String {
+    digest::to_hex(digest::sha1(sql.as_bytes()).as_slice())
+}
+
+impl<A: SqlAdapter> Migration<A> for &'static str {
+    fn checksum(&self) -> Option<String> {
+        Some(sql_checksum(*self))
+    }
+
+    fn apply(&self, db: &A) -> Result<(), String> {
+        db.try_execute(*self)
+    }
+}
+
+impl<A: SqlAdapter> Migration<A> for String {
+    fn checksum(&self) -> Option<String> {
+        Some(sql_checksum(self.as_slice()))
+    }
+
+    fn apply(&self, db: &A) -> Result<(), String> {
+        db.try_execute(self.as_slice())
+    }
+}
+
+impl<A: SqlAdapter> Migration<A> for fn(&A) -> Result<(), String> {
+    fn checksum(&self) -> Option<String> {
+        None
+    }
+
+    fn apply(&self, db: &A) -> Result<(), String> {
+        (*self)(db)
+    }
+}
+
+// A function with the state it needs, standing in for a closure.
+impl<A: SqlAdapter, S> Migration<A> for (S, fn(&S, &A) -> Result<(), String>) {
+    fn checksum(&self) -> Option<String> {
+        None
+    }
+
+    fn apply(&self, db: &A) -> Result<(), String> {
+        let (ref state, apply) = *self;
+        apply(state, db)
+    }
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum MigratorError {
+    // An applied migration whose checksum no longer matches.
+    ChecksumMismatch(String),
+    // Migration name and the error it returned.
+    MigrationFailed(String, String)
+}
+
+// The time of each migration is given by the client, as backends disagree on
+// which column types may default to the current time.
+fn ledger_query(dialect: &SqlDialect) -> String {
+    format!("CREATE TABLE IF NOT EXISTS _sql_migrations (\
+                name {} not null primary key, \
+                checksum {}, \
+                applied_at {} not null);",
+            dialect.key_typename("text"), dialect.typename("text"), dialect.typename("text"))
+}
+
+// The transaction of a run, rolled back unless committed, which includes a
+// migration failing the task.
+struct Transaction<'r, A> {
+    db: &'r A,
+    committed: bool
+}
+
+#[unsafe_destructor]
+impl<'r, A: SqlAdapter> Drop for Transaction<'r, A> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = self.db.try_execute("ROLLBACK;");
+        }
+    }
+}
+
+// Applies named migrations in the order they were added, each at most once.
+// Applied migrations are recorded in the `_sql_migrations` table, and a run
+// either applies all pending migrations or none of them, as far as the backend
+// can roll back schema changes. MySQL commits each of them implicitly.
+pub struct Migrator<A> {
+    migrations: Vec<(String, Box<Migration<A>>)>
+}
+
+impl<A: SqlAdapter> Migrator<A> {
+    pub fn new() -> Migrator<A> {
+        Migrator {
+            migrations: Vec::new()
+        }
+    }
+
+    pub fn add<M: 'static + Migration<A>>(mut self, name: &str, migration: M) -> Migrator<A> {
+        if self.migrations.iter().any(|&(ref other, _)| other.as_slice() == name) {
+            fail!("duplicate migration `{}`", name)
+        }
+        self.migrations.push((name.to_string(), box migration as Box<Migration<A>>));
+        self
+    }
+
+    pub fn run(&self, db: &A) -> Result<Vec<String>, MigratorError> {
+        db.execute(ledger_query(db.dialect()).as_slice());
+        db.execute("BEGIN;");
+        let mut transaction = Transaction { db: db, committed: false };
+        let applied: Vec<(String, Option<String>)> = unsafe {
+            db.select_columns("SELECT name, checksum FROM _sql_migrations;", []).collect()
+        };
+
+        let mut pending = Vec::new();
+        for &(ref name, ref migration) in self.migrations.iter() {
+            let checksum = migration.checksum();
+            match applied.iter().find(|&&(ref applied_name, _)| applied_name == name) {
+                None => pending.push((name, migration, checksum)),
+                Some(&(_, ref applied_checksum)) => {
+                    if applied_checksum.is_some() && *applied_checksum != checksum {
+                        return Err(ChecksumMismatch(name.clone()))
+                    }
+                }
+            }
+        }
+
+        let mut names = Vec::new();
+        for &(name, migration, ref checksum) in pending.iter() {
+            match migration.apply(db) {
+                Ok(()) => (),
+                Err(msg) => return Err(MigrationFailed(name.clone(), msg))
+            }
+            db.execute_params("INSERT INTO _sql_migrations (name, checksum, applied_at) VALUES (?, ?, ?);",
+                              [TextValue(name.clone()), checksum.value(), TextValue(time::now_utc().rfc3339())]);
+            names.push(name.clone());
+        }
+        db.execute("COMMIT;");
+        transaction.committed = true;
+
+        Ok(names)
+    }
+}
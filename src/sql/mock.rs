@@ -0,0 +1,177 @@
+// An adapter for unit tests, which runs nothing. It records every query with
+// its parameters and answers selects with rows scripted beforehand.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+
+use super::{Table, SqlValue};
+use adapter::{SqlAdapter, SqlTableIter, SqlSelectIter, ColumnInfo, SqlError, ValueCursor};
+use dialect;
+use dialect::SqlDialect;
+use sqlite;
+
+static MOCK_DIALECT: sqlite::SqliteDialect = sqlite::SqliteDialect;
+
+#[deriving(Clone, PartialEq, Show)]
+pub struct RecordedQuery {
+    pub query: String,
+    pub params: Vec<SqlValue>
+}
+
+pub struct MockAdapter {
+    queries: RefCell<Vec<RecordedQuery>>,
+    // Number of recorded queries already matched by `expect_query`.
+    checked: Cell<uint>,
+    results: RefCell<Vec<Vec<Vec<SqlValue>>>>,
+    insert_error: RefCell<Option<SqlError>>,
+    tables: RefCell<HashSet<String>>,
+    columns: RefCell<HashMap<String, Vec<ColumnInfo>>>
+}
+
+impl MockAdapter {
+    pub fn new() -> MockAdapter {
+        MockAdapter {
+            queries: RefCell::new(Vec::new()),
+            checked: Cell::new(0),
+            results: RefCell::new(Vec::new()),
+            insert_error: RefCell::new(None),
+            tables: RefCell::new(HashSet::new()),
+            columns: RefCell::new(HashMap::new())
+        }
+    }
+
+    // Scripts the rows of the next select. Selects beyond the scripted ones
+    // return no rows.
+    pub fn push_rows(&self, rows: Vec<Vec<SqlValue>>) {
+        self.results.borrow_mut().push(rows)
+    }
+
+    // Makes the next `insert_many` fail with `err`, without recording it.
+    pub fn fail_next_insert(&self, err: SqlError) {
+        *self.insert_error.borrow_mut() = Some(err)
+    }
+
+    // Pretends a table exists with the given columns. Tables of another
+    // schema are named `schema.table`.
+    pub fn add_table(&self, table: &str, columns: Vec<ColumnInfo>) {
+        self.tables.borrow_mut().insert(table.to_string());
+        self.columns.borrow_mut().insert(table.to_string(), columns);
+    }
+
+    pub fn queries(&self) -> Vec<RecordedQuery> {
+        self.queries.borrow().clone()
+    }
+
+    // Checks that the next recorded query, in the order they were run, is
+    // `query` with `params` bound.
+    pub fn expect_query(&self, query: &str, params: &[SqlValue]) {
+        let queries = self.queries.borrow();
+        let idx = self.checked.get();
+        if idx >= queries.len() {
+            fail!("expected query `{}`, but only {} queries were run", query, queries.len());
+        }
+        let recorded = queries.get(idx);
+        if recorded.query.as_slice() != query || recorded.params.as_slice() != params {
+            fail!("expected query `{}` with {}, found `{}` with {}",
+                  query, params, recorded.query, recorded.params);
+        }
+        self.checked.set(idx + 1);
+    }
+
+    // Checks that every recorded query was matched by `expect_query`.
+    pub fn expect_no_more_queries(&self) {
+        let queries = self.queries.borrow();
+        if self.checked.get() < queries.len() {
+            fail!("unexpected query `{}`", queries.get(self.checked.get()).query);
+        }
+    }
+
+    fn record(&self, query: &str, params: Vec<SqlValue>) {
+        self.queries.borrow_mut().push(RecordedQuery {
+            query: query.to_string(),
+            params: params
+        })
+    }
+
+    // The name `tables` knows the table of `T` by, in the schema it is bound to.
+    fn table_key<T:Table>(&self) -> String {
+        let schema = self.schema::<T>();
+        super::qualified_name(schema.as_ref().map(|schema| schema.as_slice()), super::table_name::<T>())
+    }
+
+    fn select(&self, query: &str, params: &[SqlValue]) -> ValueCursor {
+        self.record(query, Vec::from_slice(params));
+        let mut results = self.results.borrow_mut();
+        let rows = if results.is_empty() { Vec::new() } else { results.remove(0).unwrap() };
+        ValueCursor::new(rows)
+    }
+}
+
+impl SqlAdapter for MockAdapter {
+    fn dialect(&self) -> &'static SqlDialect {
+        &MOCK_DIALECT as &'static SqlDialect
+    }
+
+    fn try_execute(&self, query: &str) -> Result<(), String> {
+        self.record(query, Vec::new());
+        Ok(())
+    }
+
+    fn execute_params(&self, query: &str, params: &[SqlValue]) {
+        self.record(query, Vec::from_slice(params))
+    }
+
+    fn table_columns(&self, table: &str) -> Vec<ColumnInfo> {
+        match self.columns.borrow().find(&table.to_string()) {
+            Some(columns) => columns.clone(),
+            None => Vec::new()
+        }
+    }
+
+    fn table_exists<T:Table>(&self) -> bool {
+        self.tables.borrow().contains(&self.table_key::<T>())
+    }
+
+    fn create_table_if_not_exists<T:Table>(&self) {
+        self.execute(dialect::create_table_query::<T>(self.dialect(), self.schema::<T>()).as_slice());
+        for query in dialect::create_index_queries::<T>(self.dialect(), self.schema::<T>()).iter() {
+            self.execute(query.as_slice());
+        }
+        self.tables.borrow_mut().insert(self.table_key::<T>());
+    }
+
+    fn drop_table<T:Table>(&self) {
+        let table = dialect::qualified_table::<T>(self.dialect(), self.schema::<T>());
+        self.execute(format!("DROP TABLE {};", table).as_slice());
+        self.tables.borrow_mut().remove(&self.table_key::<T>());
+    }
+
+    fn drop_table_if_exists<T:Table>(&self) {
+        let table = dialect::qualified_table::<T>(self.dialect(), self.schema::<T>());
+        self.execute(format!("DROP TABLE IF EXISTS {};", table).as_slice());
+        self.tables.borrow_mut().remove(&self.table_key::<T>());
+    }
+
+    fn insert_many<'r, T:Table, Iter: Iterator<&'r T>>(&self, records: Iter) -> Result<(), SqlError> {
+        match self.insert_error.borrow_mut().take() {
+            Some(err) => return Err(err),
+            None => ()
+        }
+        let query = dialect::insert_query::<T>(self.dialect(), self.schema::<T>());
+        let mut iter = records;
+        for record in iter {
+            let cursor = ValueCursor::new(Vec::new());
+            record.bind(&cursor);
+            self.record(query.as_slice(), cursor.params());
+        }
+        Ok(())
+    }
+
+    unsafe fn select_table<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlTableIter<'r, T> {
+        SqlTableIter::new(self, box self.select(query, params))
+    }
+
+    unsafe fn select_columns<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlSelectIter<'r, T> {
+        SqlSelectIter::new(self, box self.select(query, params))
+    }
+}
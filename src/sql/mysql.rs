@@ -0,0 +1,1031 @@
+// A MySQL/MariaDB backend speaking the client/server protocol directly over
+// TCP. Statements with parameters go through the prepared-statement protocol,
+// which exchanges values in their binary form.
+
+use num::bigint::BigUint;
+use serialize::base64::FromBase64;
+use serialize::hex::FromHex;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::io::{BufferedStream, IoError};
+use std::io::net::tcp::TcpStream;
+use std::mem;
+use std::num::{One, ToStrRadix};
+use std::rand;
+use std::str;
+
+use super::{Table, SqlValue, NullValue, IntValue, TextValue, FloatValue};
+use adapter;
+use adapter::{SqlAdapter, SqlTableIter, SqlSelectIter, ColumnInfo, SqlError,
+              UniqueViolation, CheckViolation, SqlFailure, ValueCursor};
+use dialect;
+use dialect::SqlDialect;
+use digest;
+
+static CLIENT_LONG_PASSWORD: u32 = 0x1;
+static CLIENT_LONG_FLAG: u32 = 0x4;
+static CLIENT_CONNECT_WITH_DB: u32 = 0x8;
+static CLIENT_PROTOCOL_41: u32 = 0x200;
+static CLIENT_TRANSACTIONS: u32 = 0x2000;
+static CLIENT_SECURE_CONNECTION: u32 = 0x8000;
+static CLIENT_MULTI_STATEMENTS: u32 = 0x10000;
+static CLIENT_MULTI_RESULTS: u32 = 0x20000;
+static CLIENT_PLUGIN_AUTH: u32 = 0x80000;
+
+static SERVER_STATUS_IN_TRANS: u16 = 0x1;
+static SERVER_MORE_RESULTS_EXISTS: u16 = 0x8;
+
+static COM_QUERY: u8 = 0x03;
+static COM_STMT_PREPARE: u8 = 0x16;
+static COM_STMT_EXECUTE: u8 = 0x17;
+static COM_STMT_CLOSE: u8 = 0x19;
+
+static TYPE_DECIMAL: u8 = 0x00;
+static TYPE_TINY: u8 = 0x01;
+static TYPE_SHORT: u8 = 0x02;
+static TYPE_LONG: u8 = 0x03;
+static TYPE_FLOAT: u8 = 0x04;
+static TYPE_DOUBLE: u8 = 0x05;
+static TYPE_NULL: u8 = 0x06;
+static TYPE_TIMESTAMP: u8 = 0x07;
+static TYPE_LONGLONG: u8 = 0x08;
+static TYPE_INT24: u8 = 0x09;
+static TYPE_DATE: u8 = 0x0a;
+static TYPE_TIME: u8 = 0x0b;
+static TYPE_DATETIME: u8 = 0x0c;
+static TYPE_YEAR: u8 = 0x0d;
+static TYPE_NEWDECIMAL: u8 = 0xf6;
+static TYPE_VAR_STRING: u8 = 0xfd;
+
+static UNSIGNED_FLAG: u16 = 0x20;
+
+static UTF8MB4_GENERAL_CI: u8 = 45;
+static MAX_PACKET_SIZE: uint = 0xffffff;
+
+#[deriving(Clone, PartialEq, Show)]
+pub struct MysqlError {
+    pub code: u16,
+    pub state: String,
+    pub message: String
+}
+
+impl MysqlError {
+    fn new(code: u16, message: &str) -> MysqlError {
+        MysqlError {
+            code: code,
+            state: "HY000".to_string(),
+            message: message.to_string()
+        }
+    }
+}
+
+// Broken connections are reported as `CR_SERVER_LOST`, like libmysqlclient.
+fn io_error(err: IoError) -> MysqlError {
+    MysqlError::new(2013, err.to_str().as_slice())
+}
+
+// `CR_MALFORMED_PACKET`
+fn malformed_packet() -> MysqlError {
+    MysqlError::new(2027, "malformed packet from server")
+}
+
+pub type MysqlResult<T> = Result<T, MysqlError>;
+
+pub type MysqlRow = Vec<SqlValue>;
+
+pub struct MysqlConnection {
+    stream: RefCell<BufferedStream<TcpStream>>,
+    seq: Cell<u8>,
+    // The status flags of the last OK or EOF packet.
+    status: Cell<u16>
+}
+
+fn push_uint(buf: &mut Vec<u8>, value: u64, len: uint) {
+    for i in range(0, len) {
+        buf.push((value >> (8 * i)) as u8);
+    }
+}
+
+fn push_lenenc_int(buf: &mut Vec<u8>, value: u64) {
+    if value < 251 {
+        buf.push(value as u8);
+    } else if value < 1 << 16 {
+        buf.push(0xfc);
+        push_uint(buf, value, 2);
+    } else if value < 1 << 24 {
+        buf.push(0xfd);
+        push_uint(buf, value, 3);
+    } else {
+        buf.push(0xfe);
+        push_uint(buf, value, 8);
+    }
+}
+
+fn push_lenenc_str(buf: &mut Vec<u8>, value: &[u8]) {
+    push_lenenc_int(buf, value.len() as u64);
+    buf.push_all(value);
+}
+
+fn push_cstr(buf: &mut Vec<u8>, value: &str) {
+    buf.push_all(value.as_bytes());
+    buf.push(0);
+}
+
+// Reads the little-endian fields of a packet received from the server.
+// Reads past the end of the packet, or of text which is not UTF-8, return
+// zeros and empty strings and mark the packet as malformed, which `check`
+// reports once the fields are read.
+struct PacketReader<'r> {
+    buf: &'r [u8],
+    pos: uint,
+    malformed: bool
+}
+
+impl<'r> PacketReader<'r> {
+    fn new(buf: &'r [u8]) -> PacketReader<'r> {
+        PacketReader { buf: buf, pos: 0, malformed: false }
+    }
+
+    fn check(&self) -> MysqlResult<()> {
+        if self.malformed {
+            Err(malformed_packet())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read_bytes(&mut self, len: uint) -> &'r [u8] {
+        if len > self.buf.len() - self.pos {
+            self.malformed = true;
+            self.pos = self.buf.len();
+            return self.buf.slice_from(self.pos)
+        }
+        let bytes = self.buf.slice(self.pos, self.pos + len);
+        self.pos += len;
+        bytes
+    }
+
+    fn read_rest(&mut self) -> &'r [u8] {
+        let len = self.buf.len() - self.pos;
+        self.read_bytes(len)
+    }
+
+    fn read_uint(&mut self, len: uint) -> u64 {
+        self.read_bytes(len).iter().rev().fold(0u64, |acc, &b| (acc << 8) | (b as u64))
+    }
+
+    fn read_u8(&mut self) -> u8 {
+        self.read_uint(1) as u8
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        self.read_uint(2) as u16
+    }
+
+    fn read_u32(&mut self) -> u32 {
+        self.read_uint(4) as u32
+    }
+
+    fn read_lenenc_int(&mut self) -> u64 {
+        match self.read_u8() {
+            0xfc => self.read_uint(2),
+            0xfd => self.read_uint(3),
+            0xfe => self.read_uint(8),
+            len => len as u64
+        }
+    }
+
+    fn read_str(&mut self, len: uint) -> String {
+        match str::from_utf8(self.read_bytes(len)) {
+            Some(s) => s.to_string(),
+            None => {
+                self.malformed = true;
+                String::new()
+            }
+        }
+    }
+
+    fn read_lenenc_str(&mut self) -> String {
+        let len = self.read_lenenc_int();
+        self.read_str(len as uint)
+    }
+
+    fn read_cstr(&mut self) -> String {
+        let len = match self.buf.slice_from(self.pos).iter().position(|&b| b == 0) {
+            Some(len) => len,
+            None => self.buf.len() - self.pos
+        };
+        let s = self.read_str(len);
+        self.pos = cmp::min(self.pos + 1, self.buf.len());
+        s
+    }
+}
+
+fn is_eof(packet: &[u8]) -> bool {
+    packet.len() < 9 && packet[0] == 0xfe
+}
+
+fn parse_error(packet: &[u8]) -> MysqlError {
+    let mut reader = PacketReader::new(packet);
+    reader.read_u8();
+    let code = reader.read_u16();
+    let state = if reader.buf.get(reader.pos) == Some(&('#' as u8)) {
+        reader.read_u8();
+        reader.read_str(5)
+    } else {
+        "HY000".to_string()
+    };
+    let len = reader.buf.len() - reader.pos;
+    MysqlError {
+        code: code,
+        state: state,
+        message: reader.read_str(len)
+    }
+}
+
+// The status flags of an OK or EOF packet.
+fn status_flags(packet: &[u8]) -> u16 {
+    let mut reader = PacketReader::new(packet);
+    if reader.read_u8() == 0 {
+        reader.read_lenenc_int();
+        reader.read_lenenc_int();
+    } else {
+        reader.read_u16();
+    }
+    reader.read_u16()
+}
+
+// mysql_native_password: SHA1(password) XOR SHA1(scramble + SHA1(SHA1(password)))
+pub fn native_password(password: Option<&str>, scramble: &[u8]) -> Vec<u8> {
+    let password = match password {
+        Some(password) if !password.is_empty() => password,
+        _ => return Vec::new()
+    };
+    let stage1 = digest::sha1(password.as_bytes());
+    let stage2 = digest::sha1(stage1.as_slice());
+    let mut salted = Vec::from_slice(scramble);
+    salted.push_all(stage2.as_slice());
+    let mask = digest::sha1(salted.as_slice());
+    stage1.iter().zip(mask.iter()).map(|(a, b)| *a ^ *b).collect()
+}
+
+// caching_sha2_password: SHA256(password) XOR SHA256(SHA256(SHA256(password)) + scramble)
+pub fn sha2_password(password: Option<&str>, scramble: &[u8]) -> Vec<u8> {
+    let password = match password {
+        Some(password) if !password.is_empty() => password,
+        _ => return Vec::new()
+    };
+    let stage1 = digest::sha256(password.as_bytes());
+    let mut salted = digest::sha256(stage1.as_slice());
+    salted.push_all(scramble);
+    let mask = digest::sha256(salted.as_slice());
+    stage1.iter().zip(mask.iter()).map(|(a, b)| *a ^ *b).collect()
+}
+
+fn auth_response(plugin: &str, password: Option<&str>, scramble: &[u8]) -> MysqlResult<Vec<u8>> {
+    match plugin {
+        "mysql_native_password" => Ok(native_password(password, scramble)),
+        "caching_sha2_password" => Ok(sha2_password(password, scramble)),
+        _ => Err(MysqlError::new(2059, format!("unsupported authentication plugin {}", plugin).as_slice()))
+    }
+}
+
+macro_rules! try_some(
+    ($e:expr) => (match $e { Some(value) => value, None => return None })
+)
+
+// The tag and contents of the DER value `der` starts with, and what follows it.
+fn der_value<'r>(der: &'r [u8]) -> Option<(u8, &'r [u8], &'r [u8])> {
+    if der.len() < 2 {
+        return None
+    }
+    let (len, header) = if der[1] < 0x80 {
+        (der[1] as uint, 2u)
+    } else {
+        let count = (der[1] & 0x7f) as uint;
+        if der.len() < 2 + count {
+            return None
+        }
+        (der.slice(2, 2 + count).iter().fold(0u, |acc, &b| (acc << 8) | b as uint), 2 + count)
+    };
+    if der.len() < header + len {
+        return None
+    }
+    Some((der[0], der.slice(header, header + len), der.slice_from(header + len)))
+}
+
+// The modulus and public exponent of a PEM encoded RSA SubjectPublicKeyInfo.
+pub fn rsa_public_key(pem: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+    let body: Vec<&str> = pem.lines().filter(|line| !line.starts_with("-----")).collect();
+    let der = try_some!(body.concat().as_slice().from_base64().ok());
+    let (_, info, _) = try_some!(der_value(der.as_slice()));
+    let (_, _, key_info) = try_some!(der_value(info));
+    let (_, bits, _) = try_some!(der_value(key_info));
+    // The bit string starts with its count of unused bits.
+    let (_, key, _) = try_some!(der_value(bits.slice_from(cmp::min(1, bits.len()))));
+    let (_, modulus, rest) = try_some!(der_value(key));
+    let (_, exponent, _) = try_some!(der_value(rest));
+    Some((Vec::from_slice(modulus), Vec::from_slice(exponent)))
+}
+
+fn to_biguint(bytes: &[u8]) -> BigUint {
+    BigUint::parse_bytes(digest::to_hex(bytes).as_bytes(), 16).unwrap_or(BigUint::new(Vec::new()))
+}
+
+// The big-endian bytes of `n`, left-padded to `len`.
+fn from_biguint(n: &BigUint, len: uint) -> Vec<u8> {
+    let hex = n.to_str_radix(16);
+    let mut padded = String::from_char(len * 2 - cmp::min(len * 2, hex.len()), '0');
+    padded.push_str(hex.as_slice());
+    padded.as_slice().from_hex().unwrap()
+}
+
+fn mod_pow(base: &BigUint, exponent: &[u8], modulus: &BigUint) -> BigUint {
+    let mut result: BigUint = One::one();
+    for byte in exponent.iter() {
+        for bit in range(0u, 8).rev() {
+            result = (result * result) % *modulus;
+            if (*byte >> bit) & 1 == 1 {
+                result = (result * *base) % *modulus;
+            }
+        }
+    }
+    result
+}
+
+fn mgf1(seed: &[u8], len: uint) -> Vec<u8> {
+    let mut mask = Vec::new();
+    let mut counter = 0u32;
+    while mask.len() < len {
+        let mut input = Vec::from_slice(seed);
+        for shift in [24u32, 16, 8, 0].iter() {
+            input.push((counter >> *shift) as u8);
+        }
+        mask.push_all(digest::sha1(input.as_slice()).as_slice());
+        counter += 1;
+    }
+    mask.truncate(len);
+    mask
+}
+
+// RSAES-OAEP with SHA-1 and an empty label, which is how MySQL expects the
+// password when it can't be sent in the clear. `seed` is 20 random bytes.
+pub fn rsa_encrypt(pem: &str, message: &[u8], seed: &[u8]) -> Option<Vec<u8>> {
+    let (modulus, exponent) = try_some!(rsa_public_key(pem));
+    // DER integers carry a leading zero when their top bit is set.
+    let len = modulus.len() - modulus.iter().take_while(|byte| **byte == 0).count();
+    let modulus = to_biguint(modulus.as_slice());
+    if message.len() + 42 > len || seed.len() != 20 {
+        return None
+    }
+    let mut db = digest::sha1([]);
+    db.grow(len - message.len() - 42, &0u8);
+    db.push(1);
+    db.push_all(message);
+    let db_mask = mgf1(seed, len - 21);
+    let masked_db: Vec<u8> = db.iter().zip(db_mask.iter()).map(|(a, b)| *a ^ *b).collect();
+    let seed_mask = mgf1(masked_db.as_slice(), 20);
+    let mut encoded = vec![0u8];
+    encoded.extend(seed.iter().zip(seed_mask.iter()).map(|(a, b)| *a ^ *b));
+    encoded.push_all(masked_db.as_slice());
+    let encrypted = mod_pow(&to_biguint(encoded.as_slice()), exponent.as_slice(), &modulus);
+    Some(from_biguint(&encrypted, len))
+}
+
+// Where caching_sha2_password gets the RSA public key of the server, which it
+// encrypts the password with over connections without TLS.
+pub enum ServerKey {
+    // Refuses to send the password, as `connect` does.
+    NoServerKey,
+    // The PEM of the key the server was configured with.
+    PinnedKey(String),
+    // Asks the server for its key in the clear, which lets a man in the middle
+    // answer with a key of its own and read the password.
+    RetrieveServerKey
+}
+
+pub fn connect(host: &str, port: u16, user: &str, password: Option<&str>, database: &str)
+        -> MysqlResult<MysqlConnection> {
+    connect_with_key(host, port, user, password, database, NoServerKey)
+}
+
+pub fn connect_with_key(host: &str, port: u16, user: &str, password: Option<&str>, database: &str,
+                        server_key: ServerKey) -> MysqlResult<MysqlConnection> {
+    let stream = match TcpStream::connect(host, port) {
+        Ok(stream) => stream,
+        Err(e) => return Err(io_error(e))
+    };
+    let conn = MysqlConnection {
+        stream: RefCell::new(BufferedStream::new(stream)),
+        seq: Cell::new(0),
+        status: Cell::new(0)
+    };
+
+    let handshake = try!(conn.receive());
+    if *handshake.get(0) == 0xff {
+        return Err(parse_error(handshake.as_slice()));
+    }
+    let mut reader = PacketReader::new(handshake.as_slice());
+    if reader.read_u8() != 10 {
+        return Err(MysqlError::new(2027, "unsupported protocol version"));
+    }
+    reader.read_cstr();
+    reader.read_u32();
+    let mut scramble = Vec::from_slice(reader.read_bytes(8));
+    reader.read_u8();
+    let mut capabilities = reader.read_u16() as u32;
+    reader.read_u8();
+    reader.read_u16();
+    capabilities |= (reader.read_u16() as u32) << 16;
+    let scramble_len = reader.read_u8() as uint;
+    reader.read_bytes(10);
+    if capabilities & CLIENT_SECURE_CONNECTION != 0 {
+        let len = cmp::max(13, scramble_len as int - 8) as uint;
+        scramble.push_all(reader.read_bytes(len - 1));
+        reader.read_u8();
+    }
+    let mut plugin = if capabilities & CLIENT_PLUGIN_AUTH != 0 {
+        reader.read_cstr()
+    } else {
+        "mysql_native_password".to_string()
+    };
+    try!(reader.check());
+
+    let flags = CLIENT_LONG_PASSWORD | CLIENT_LONG_FLAG | CLIENT_CONNECT_WITH_DB | CLIENT_PROTOCOL_41 |
+                CLIENT_TRANSACTIONS | CLIENT_SECURE_CONNECTION | CLIENT_MULTI_STATEMENTS |
+                CLIENT_MULTI_RESULTS | CLIENT_PLUGIN_AUTH;
+    let mut response = Vec::new();
+    push_uint(&mut response, flags as u64, 4);
+    push_uint(&mut response, MAX_PACKET_SIZE as u64, 4);
+    response.push(UTF8MB4_GENERAL_CI);
+    response.push_all([0u8, ..23]);
+    push_cstr(&mut response, user);
+    if plugin.as_slice() != "caching_sha2_password" {
+        plugin = "mysql_native_password".to_string();
+    }
+    let auth = try!(auth_response(plugin.as_slice(), password, scramble.as_slice()));
+    response.push(auth.len() as u8);
+    response.push_all(auth.as_slice());
+    push_cstr(&mut response, database);
+    push_cstr(&mut response, plugin.as_slice());
+    try!(conn.send(response.as_slice()));
+
+    loop {
+        let packet = try!(conn.receive());
+        match *packet.get(0) {
+            0x00 => break,
+            0xff => return Err(parse_error(packet.as_slice())),
+            // The server asks to restart authentication with another plugin.
+            0xfe => {
+                let mut reader = PacketReader::new(packet.as_slice());
+                reader.read_u8();
+                plugin = reader.read_cstr();
+                let data = reader.read_rest();
+                scramble = Vec::from_slice(data.slice_to(cmp::min(data.len(), 20)));
+                if scramble.is_empty() {
+                    return Err(malformed_packet());
+                }
+                try!(conn.send(try!(auth_response(plugin.as_slice(), password, scramble.as_slice())).as_slice()));
+            },
+            // caching_sha2_password accepted the scramble from its cache, or
+            // needs the password itself, which it takes encrypted with its
+            // public key over connections without TLS.
+            0x01 if plugin.as_slice() == "caching_sha2_password" && packet.len() > 1 => match *packet.get(1) {
+                0x03 => (),
+                0x04 => {
+                    let pem = match server_key {
+                        NoServerKey => return Err(MysqlError::new(2061,
+                            "the server needs the password encrypted with its public key, which is neither pinned nor allowed to be retrieved")),
+                        PinnedKey(ref pem) => pem.clone(),
+                        RetrieveServerKey => {
+                            try!(conn.send([0x02]));
+                            let key = try!(conn.receive());
+                            str::from_utf8(key.slice_from(cmp::min(1, key.len()))).unwrap_or("").to_string()
+                        }
+                    };
+                    let mut message = Vec::from_slice(password.unwrap_or("").as_bytes());
+                    message.push(0);
+                    for (i, byte) in message.mut_iter().enumerate() {
+                        *byte ^= *scramble.get(i % scramble.len());
+                    }
+                    let seed: Vec<u8> = range(0u, 20).map(|_| rand::random::<u8>()).collect();
+                    match rsa_encrypt(pem.as_slice(), message.as_slice(), seed.as_slice()) {
+                        Some(encrypted) => try!(conn.send(encrypted.as_slice())),
+                        None => return Err(MysqlError::new(2061, "cannot encrypt the password with the server's public key"))
+                    }
+                },
+                _ => return Err(MysqlError::new(2059, "unsupported authentication exchange"))
+            },
+            _ => return Err(MysqlError::new(2059, "unsupported authentication exchange"))
+        }
+    }
+
+    // SQL `||` concatenates strings, where MySQL takes it for a logical OR.
+    try!(conn.simple_query("SET SESSION sql_mode = CONCAT_WS(',', NULLIF(@@sql_mode, ''), 'PIPES_AS_CONCAT');"));
+    Ok(conn)
+}
+
+impl MysqlConnection {
+    fn send(&self, payload: &[u8]) -> MysqlResult<()> {
+        let mut stream = self.stream.borrow_mut();
+        let mut header = Vec::new();
+        push_uint(&mut header, payload.len() as u64, 3);
+        header.push(self.seq.get());
+        self.seq.set(self.seq.get() + 1);
+        try!(stream.write(header.as_slice()).map_err(io_error));
+        try!(stream.write(payload).map_err(io_error));
+        stream.flush().map_err(io_error)
+    }
+
+    fn receive(&self) -> MysqlResult<Vec<u8>> {
+        let mut stream = self.stream.borrow_mut();
+        let mut payload = Vec::new();
+        loop {
+            let header = try!(stream.read_exact(4).map_err(io_error));
+            let len = PacketReader::new(header.as_slice()).read_uint(3) as uint;
+            self.seq.set(*header.get(3) + 1);
+            payload.push_all_move(try!(stream.read_exact(len).map_err(io_error)));
+            // Payloads of 16MB and over are split across packets.
+            if len < MAX_PACKET_SIZE {
+                // Every packet of the server starts with a header byte.
+                return if payload.is_empty() { Err(malformed_packet()) } else { Ok(payload) };
+            }
+        }
+    }
+
+    fn command(&self, command: u8, payload: &[u8]) -> MysqlResult<()> {
+        let mut packet = vec![command];
+        packet.push_all(payload);
+        self.seq.set(0);
+        self.send(packet.as_slice())
+    }
+
+    // Reads packets up to the next EOF packet, returning them without it.
+    fn receive_until_eof(&self) -> MysqlResult<(Vec<Vec<u8>>, u16)> {
+        let mut packets = Vec::new();
+        loop {
+            let packet = try!(self.receive());
+            if is_eof(packet.as_slice()) {
+                self.status.set(status_flags(packet.as_slice()));
+                return Ok((packets, self.status.get()));
+            } else if *packet.get(0) == 0xff {
+                return Err(parse_error(packet.as_slice()));
+            }
+            packets.push(packet);
+        }
+    }
+
+    // Runs one or more statements without parameters.
+    pub fn simple_query(&self, query: &str) -> MysqlResult<()> {
+        try!(self.command(COM_QUERY, query.as_bytes()));
+        loop {
+            let packet = try!(self.receive());
+            let status = match *packet.get(0) {
+                0x00 => {
+                    self.status.set(status_flags(packet.as_slice()));
+                    self.status.get()
+                },
+                0xff => return Err(parse_error(packet.as_slice())),
+                _ => {
+                    try!(self.receive_until_eof());
+                    let (_, status) = try!(self.receive_until_eof());
+                    status
+                }
+            };
+            if status & SERVER_MORE_RESULTS_EXISTS == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    // Returns the id of a new prepared statement and its number of parameters.
+    fn prepare(&self, query: &str) -> MysqlResult<(u32, uint)> {
+        try!(self.command(COM_STMT_PREPARE, query.as_bytes()));
+        let packet = try!(self.receive());
+        if *packet.get(0) == 0xff {
+            return Err(parse_error(packet.as_slice()));
+        }
+        let mut reader = PacketReader::new(packet.as_slice());
+        reader.read_u8();
+        let id = reader.read_u32();
+        let columns = reader.read_u16();
+        let params = reader.read_u16();
+        try!(reader.check());
+        if params > 0 {
+            try!(self.receive_until_eof());
+        }
+        if columns > 0 {
+            try!(self.receive_until_eof());
+        }
+        Ok((id, params as uint))
+    }
+
+    fn close(&self, id: u32) -> MysqlResult<()> {
+        let mut payload = Vec::new();
+        push_uint(&mut payload, id as u64, 4);
+        self.command(COM_STMT_CLOSE, payload.as_slice())
+    }
+
+    fn execute_statement(&self, id: u32, params: &[SqlValue]) -> MysqlResult<Vec<MysqlRow>> {
+        let mut payload = Vec::new();
+        push_uint(&mut payload, id as u64, 4);
+        payload.push(0);
+        push_uint(&mut payload, 1, 4);
+        if !params.is_empty() {
+            let mut null_bitmap = Vec::from_elem((params.len() + 7) / 8, 0u8);
+            let mut types = Vec::new();
+            let mut values = Vec::new();
+            for (i, param) in params.iter().enumerate() {
+                let ty = match *param {
+                    NullValue => {
+                        *null_bitmap.get_mut(i / 8) |= 1 << (i % 8);
+                        TYPE_NULL
+                    },
+                    IntValue(v) => {
+                        push_uint(&mut values, v as u64, 8);
+                        TYPE_LONGLONG
+                    },
+                    TextValue(ref v) => {
+                        push_lenenc_str(&mut values, v.as_bytes());
+                        TYPE_VAR_STRING
+                    },
+                    FloatValue(v) => {
+                        push_uint(&mut values, unsafe { mem::transmute::<f64, u64>(v) }, 8);
+                        TYPE_DOUBLE
+                    }
+                };
+                types.push(ty);
+                types.push(0);
+            }
+            payload.push_all(null_bitmap.as_slice());
+            payload.push(1);
+            payload.push_all(types.as_slice());
+            payload.push_all(values.as_slice());
+        }
+        try!(self.command(COM_STMT_EXECUTE, payload.as_slice()));
+
+        let packet = try!(self.receive());
+        match *packet.get(0) {
+            0x00 => {
+                self.status.set(status_flags(packet.as_slice()));
+                return Ok(Vec::new())
+            },
+            0xff => return Err(parse_error(packet.as_slice())),
+            _ => ()
+        }
+        let (column_defs, _) = try!(self.receive_until_eof());
+        let mut columns = Vec::new();
+        for def in column_defs.iter() {
+            columns.push(try!(column_type(def.as_slice())));
+        }
+        let (rows, _) = try!(self.receive_until_eof());
+        let mut result = Vec::new();
+        for row in rows.iter() {
+            result.push(try!(parse_binary_row(columns.as_slice(), row.as_slice())));
+        }
+        Ok(result)
+    }
+
+    pub fn query(&self, query: &str, params: &[SqlValue]) -> MysqlResult<Vec<MysqlRow>> {
+        let (id, _) = try!(self.prepare(query));
+        let result = self.execute_statement(id, params);
+        try!(self.close(id));
+        result
+    }
+
+}
+
+// `SqlAdapter` has no errors for the schema and select methods, which fail the
+// task as the other adapters do. The `try_` methods and `query` return them.
+fn or_fail<T>(result: MysqlResult<T>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => fail!("{}", e.message)
+    }
+}
+
+// The type and flags of a column definition.
+fn column_type(def: &[u8]) -> MysqlResult<(u8, u16)> {
+    let mut reader = PacketReader::new(def);
+    for _ in range(0u, 6) {
+        reader.read_lenenc_str();
+    }
+    reader.read_lenenc_int();
+    reader.read_u16();
+    reader.read_u32();
+    let ty = reader.read_u8();
+    let flags = reader.read_u16();
+    try!(reader.check());
+    Ok((ty, flags))
+}
+
+fn parse_binary_row(columns: &[(u8, u16)], row: &[u8]) -> MysqlResult<MysqlRow> {
+    let mut reader = PacketReader::new(row);
+    reader.read_u8();
+    // The NULL bitmap of result rows starts at bit 2.
+    let null_bitmap = reader.read_bytes((columns.len() + 9) / 8);
+    try!(reader.check());
+    let mut values = Vec::new();
+    for (i, &(ty, flags)) in columns.iter().enumerate() {
+        if null_bitmap[(i + 2) / 8] & (1 << ((i + 2) % 8)) != 0 {
+            values.push(NullValue);
+            continue;
+        }
+        let unsigned = flags & UNSIGNED_FLAG != 0;
+        values.push(match ty {
+            TYPE_TINY => IntValue(if unsigned { reader.read_u8() as int } else { reader.read_u8() as i8 as int }),
+            TYPE_SHORT | TYPE_YEAR =>
+                IntValue(if unsigned { reader.read_u16() as int } else { reader.read_u16() as i16 as int }),
+            TYPE_LONG | TYPE_INT24 =>
+                IntValue(if unsigned { reader.read_u32() as int } else { reader.read_u32() as i32 as int }),
+            TYPE_LONGLONG => IntValue(reader.read_uint(8) as int),
+            TYPE_FLOAT => FloatValue(unsafe { mem::transmute::<u32, f32>(reader.read_u32()) } as f64),
+            TYPE_DOUBLE => FloatValue(unsafe { mem::transmute::<u64, f64>(reader.read_uint(8)) }),
+            TYPE_DATE | TYPE_DATETIME | TYPE_TIMESTAMP => TextValue(read_datetime(&mut reader, ty == TYPE_DATE)),
+            TYPE_TIME => TextValue(read_time(&mut reader)),
+            // Decimals are length-encoded strings, read as reals like SQLite does.
+            TYPE_DECIMAL | TYPE_NEWDECIMAL => {
+                let decimal = reader.read_lenenc_str();
+                match from_str::<f64>(decimal.as_slice()) {
+                    Some(value) => FloatValue(value),
+                    None => return Err(MysqlError::new(2027, format!("invalid decimal {} at column {}",
+                                                                     decimal, i).as_slice()))
+                }
+            },
+            // Strings and blobs are length-encoded strings.
+            _ => TextValue(reader.read_lenenc_str())
+        });
+    }
+    try!(reader.check());
+    Ok(values)
+}
+
+// Dates and times are sent as structs, and returned as text in the format
+// MySQL uses for them.
+fn read_datetime(reader: &mut PacketReader, date_only: bool) -> String {
+    let len = reader.read_u8() as uint;
+    let mut fields = PacketReader::new(reader.read_bytes(len));
+    let date = if len >= 4 {
+        format!("{:04}-{:02}-{:02}", fields.read_u16(), fields.read_u8(), fields.read_u8())
+    } else {
+        "0000-00-00".to_string()
+    };
+    if date_only {
+        return date
+    }
+    let mut text = if len >= 7 {
+        format!("{} {:02}:{:02}:{:02}", date, fields.read_u8(), fields.read_u8(), fields.read_u8())
+    } else {
+        format!("{} 00:00:00", date)
+    };
+    if len >= 11 {
+        text.push_str(format!(".{:06}", fields.read_u32()).as_slice());
+    }
+    text
+}
+
+fn read_time(reader: &mut PacketReader) -> String {
+    let len = reader.read_u8() as uint;
+    let mut fields = PacketReader::new(reader.read_bytes(len));
+    if len < 8 {
+        return "00:00:00".to_string()
+    }
+    let sign = if fields.read_u8() == 1 { "-" } else { "" };
+    let hours = fields.read_u32() * 24 + fields.read_u8() as u32;
+    let mut text = format!("{}{:02}:{:02}:{:02}", sign, hours, fields.read_u8(), fields.read_u8());
+    if len >= 12 {
+        text.push_str(format!(".{:06}", fields.read_u32()).as_slice());
+    }
+    text
+}
+
+// Note that MySQL parses but ignores `REFERENCES` clauses written inside
+// column definitions, so `#[sql_references]` is not enforced there.
+pub struct MysqlDialect;
+
+static MYSQL_DIALECT: MysqlDialect = MysqlDialect;
+
+impl SqlDialect for MysqlDialect {
+    fn name(&self) -> &'static str {
+        "mysql"
+    }
+
+    fn typename(&self, typename: &str) -> String {
+        match typename {
+            "int" => "bigint".to_string(),
+            "real" => "double".to_string(),
+            _ => typename.to_string()
+        }
+    }
+
+    // `text` columns can't be keys or indexed without a prefix length.
+    fn key_typename(&self, typename: &str) -> String {
+        match typename {
+            "text" => "varchar(255)".to_string(),
+            _ => self.typename(typename)
+        }
+    }
+
+    fn quote_identifier(&self, name: &str) -> String {
+        format!("`{}`", name.replace("`", "``"))
+    }
+
+    fn placeholder(&self, _: uint) -> String {
+        "?".to_string()
+    }
+
+    // MySQL has neither `IF NOT EXISTS` nor partial indexes. The index over
+    // every row serves the same queries, and existing ones are skipped by
+    // `create_table_if_not_exists`.
+    fn create_index_query(&self, on: &str, columns: &str, _: Option<&str>) -> String {
+        format!("CREATE INDEX {} ({});", on, columns)
+    }
+}
+
+fn sql_typename(data_type: &str) -> String {
+    match data_type {
+        "bigint" | "int" | "mediumint" | "smallint" | "tinyint" => "int".to_string(),
+        "double" | "float" => "real".to_string(),
+        "varchar" | "char" | "text" | "mediumtext" | "longtext" => "text".to_string(),
+        _ => data_type.to_string()
+    }
+}
+
+// The name quoted in messages such as `for key 'T.name'` or
+// `CONSTRAINT `name` failed for ...`.
+fn quoted_name(msg: &str) -> Option<String> {
+    let start = match msg.find(|c: char| c == '\'' || c == '`') {
+        Some(start) => start,
+        None => return None
+    };
+    let quote = msg.char_at(start);
+    msg.slice_from(start + 1).find(quote).map(|len| {
+        let name = msg.slice(start + 1, start + 1 + len);
+        match name.rfind('.') {
+            Some(dot) => name.slice_from(dot + 1).to_string(),
+            None => name.to_string()
+        }
+    })
+}
+
+impl MysqlConnection {
+    fn constraint_error(&self, table: &str, err: MysqlError) -> SqlError {
+        match err.code {
+            // ER_DUP_ENTRY only names the key, so look up its columns.
+            1062 => {
+                let msg = err.message.as_slice();
+                let key = match msg.find_str(" for key ") {
+                    Some(pos) => quoted_name(msg.slice_from(pos)).unwrap_or(String::new()),
+                    None => String::new()
+                };
+                let rows = match self.query(
+                    "SELECT column_name FROM information_schema.statistics \
+                     WHERE table_schema = DATABASE() AND table_name = ? AND index_name = ? \
+                     ORDER BY seq_in_index;",
+                    [TextValue(table.to_string()), TextValue(key)]) {
+                    Ok(rows) => rows,
+                    Err(_) => return SqlFailure(err.message.clone())
+                };
+                UniqueViolation {
+                    table: table.to_string(),
+                    columns: rows.move_iter().filter_map(|row| {
+                        match row.as_slice().head() {
+                            Some(&TextValue(ref name)) => Some(name.clone()),
+                            _ => None
+                        }
+                    }).collect()
+                }
+            },
+            // ER_CONSTRAINT_FAILED on MariaDB, ER_CHECK_CONSTRAINT_VIOLATED on MySQL
+            4025 | 3819 => CheckViolation {
+                table: table.to_string(),
+                constraint: quoted_name(err.message.as_slice()).unwrap_or(String::new())
+            },
+            _ => SqlFailure(err.message)
+        }
+    }
+
+    pub fn try_table_columns(&self, table: &str) -> MysqlResult<Vec<ColumnInfo>> {
+        let rows = try!(self.query(
+            "SELECT column_name, data_type, is_nullable, column_default, column_key \
+             FROM information_schema.columns \
+             WHERE table_schema = DATABASE() AND table_name = ? \
+             ORDER BY ordinal_position;",
+            [TextValue(table.to_string())]));
+        let mut columns = Vec::new();
+        for row in rows.move_iter() {
+            let text = |idx: uint| match row.as_slice().get(idx) {
+                Some(&TextValue(ref s)) => Some(s.clone()),
+                _ => None
+            };
+            let (name, typename) = match (text(0), text(1)) {
+                (Some(name), Some(typename)) => (name, typename),
+                _ => return Err(malformed_packet())
+            };
+            columns.push(ColumnInfo {
+                name: name,
+                typename: sql_typename(typename.as_slice()),
+                notnull: text(2) == Some("NO".to_string()),
+                default: text(3),
+                primary_key: text(4) == Some("PRI".to_string())
+            });
+        }
+        Ok(columns)
+    }
+
+    pub fn try_table_exists<T:Table>(&self) -> MysqlResult<bool> {
+        let rows = try!(self.query(
+            "SELECT count(*) FROM information_schema.tables \
+             WHERE table_schema = DATABASE() AND table_name = ?;",
+            [TextValue(super::table_name::<T>().to_string())]));
+        Ok(match rows.as_slice().head().and_then(|row| row.as_slice().head()) {
+            Some(count) => *count != IntValue(0),
+            None => false
+        })
+    }
+
+    // Creates the table and those of its indexes which don't exist yet, as
+    // MySQL has no `CREATE INDEX IF NOT EXISTS`.
+    pub fn try_create_table<T:Table>(&self) -> MysqlResult<()> {
+        try!(self.simple_query(dialect::create_table_query::<T>(self.dialect(), self.schema::<T>()).as_slice()));
+        let rows = try!(self.query(
+            "SELECT DISTINCT index_name FROM information_schema.statistics \
+             WHERE table_schema = DATABASE() AND table_name = ?;",
+            [TextValue(super::table_name::<T>().to_string())]));
+        let existing: Vec<&SqlValue> = rows.iter().filter_map(|row| row.as_slice().head()).collect();
+        let queries = dialect::create_index_queries::<T>(self.dialect(), self.schema::<T>());
+        for (index, query) in super::indexes::<T>().iter().zip(queries.iter()) {
+            if !existing.iter().any(|name| **name == TextValue(index.name.to_string())) {
+                try!(self.simple_query(query.as_slice()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SqlAdapter for MysqlConnection {
+    fn dialect(&self) -> &'static SqlDialect {
+        &MYSQL_DIALECT as &'static SqlDialect
+    }
+
+    fn try_execute(&self, query: &str) -> Result<(), String> {
+        match self.simple_query(query) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.message)
+        }
+    }
+
+    fn execute_params(&self, query: &str, params: &[SqlValue]) {
+        or_fail(self.query(query, params));
+    }
+
+    fn table_columns(&self, table: &str) -> Vec<ColumnInfo> {
+        or_fail(self.try_table_columns(table))
+    }
+
+    fn table_exists<T:Table>(&self) -> bool {
+        or_fail(self.try_table_exists::<T>())
+    }
+
+    fn create_table_if_not_exists<T:Table>(&self) {
+        or_fail(self.try_create_table::<T>())
+    }
+
+    fn insert_many<'r, T:Table, Iter: Iterator<&'r T>>(&self, records: Iter) -> Result<(), SqlError> {
+        let mut iter = records;
+        // With autocommit a savepoint ends with the statement that set it.
+        let begin = self.status.get() & SERVER_STATUS_IN_TRANS == 0;
+        adapter::with_savepoint(self, begin, || {
+            let (id, _) = match self.prepare(dialect::insert_query::<T>(self.dialect(), self.schema::<T>()).as_slice()) {
+                Ok(stmt) => stmt,
+                Err(e) => return Err(SqlFailure(e.message))
+            };
+            let mut result = Ok(());
+            for record in iter.by_ref() {
+                let cursor = ValueCursor::new(Vec::new());
+                record.bind(&cursor);
+                match self.execute_statement(id, cursor.params().as_slice()) {
+                    Ok(_) => (),
+                    Err(e) => {
+                        result = Err(self.constraint_error(super::table_name::<T>(), e));
+                        break;
+                    }
+                }
+            }
+            match self.close(id) {
+                Err(e) if result.is_ok() => Err(SqlFailure(e.message)),
+                _ => result
+            }
+        })
+    }
+
+    unsafe fn select_table<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlTableIter<'r, T> {
+        let rows = or_fail(self.query(query, params));
+        SqlTableIter::new(self, box ValueCursor::new(rows))
+    }
+
+    unsafe fn select_columns<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlSelectIter<'r, T> {
+        let rows = or_fail(self.query(query, params));
+        SqlSelectIter::new(self, box ValueCursor::new(rows))
+    }
+}
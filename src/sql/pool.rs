@@ -0,0 +1,361 @@
+// A pool of connections shared between tasks. `Pool::get` hands out a
+// `PooledConnection`, which is itself a `SqlAdapter` and goes back to the pool
+// when dropped.
+
+use std::intrinsics::TypeId;
+use std::io::timer;
+use std::task;
+use sync::{Arc, Mutex};
+use time;
+
+use super::{Table, SqlValue};
+use adapter::{SqlAdapter, SqlTableIter, SqlSelectIter, ColumnInfo, SqlError};
+use dialect::SqlDialect;
+
+// Opens the connections of a pool.
+pub trait ConnectionFactory<A>: Send + Share {
+    fn connect(&self) -> Result<A, String>;
+}
+
+impl<A> ConnectionFactory<A> for fn() -> Result<A, String> {
+    fn connect(&self) -> Result<A, String> {
+        (*self)()
+    }
+}
+
+// Prepares every new connection of a pool, as for pragmas.
+pub trait ConnectionInit<A> {
+    fn init(&self, conn: &A) -> Result<(), String>;
+}
+
+impl<A> ConnectionInit<A> for fn(&A) -> Result<(), String> {
+    fn init(&self, conn: &A) -> Result<(), String> {
+        (*self)(conn)
+    }
+}
+
+// A function with the state it needs, standing in for a closure.
+impl<A, S> ConnectionInit<A> for (S, fn(&S, &A) -> Result<(), String>) {
+    fn init(&self, conn: &A) -> Result<(), String> {
+        let (ref state, init) = *self;
+        init(state, conn)
+    }
+}
+
+// A query run on every new connection.
+impl<A: SqlAdapter> ConnectionInit<A> for String {
+    fn init(&self, conn: &A) -> Result<(), String> {
+        conn.try_execute(self.as_slice())
+    }
+}
+
+pub struct PoolConfig<A> {
+    pub max_size: uint,
+    // Connections kept idle, opened by `Pool::new` and again after
+    // checkouts, as far as `max_size` allows. `Pool::new` rejects more of
+    // them than `max_size`.
+    pub min_idle: uint,
+    pub checkout_timeout_ms: u64,
+    // Run on an idle connection before handing it out. Connections it fails
+    // on are closed and replaced.
+    pub health_check: Option<String>,
+    // Set with `set_init`.
+    pub init: Option<Box<ConnectionInit<A>:Send+Share>>
+}
+
+impl<A> PoolConfig<A> {
+    pub fn new() -> PoolConfig<A> {
+        PoolConfig {
+            max_size: 10,
+            min_idle: 0,
+            checkout_timeout_ms: 30000,
+            health_check: None,
+            init: None
+        }
+    }
+
+    pub fn set_init<I: ConnectionInit<A> + Send + Share>(&mut self, init: I) {
+        self.init = Some(box init as Box<ConnectionInit<A>:Send+Share>);
+    }
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum PoolError {
+    ConnectError(String),
+    CheckoutTimeout,
+    InvalidConfig(String)
+}
+
+struct PoolState<A> {
+    idle: Vec<A>,
+    // Open connections, idle or checked out.
+    size: uint
+}
+
+enum Checkout<A> {
+    Idle(A),
+    // Room was made in `size` for a new connection.
+    Reserved,
+    TimedOut
+}
+
+struct PoolInner<A, F> {
+    factory: F,
+    config: PoolConfig<A>,
+    state: Mutex<PoolState<A>>
+}
+
+pub struct Pool<A, F> {
+    inner: Arc<PoolInner<A, F>>
+}
+
+impl<A: SqlAdapter + Send, F: ConnectionFactory<A>> Pool<A, F> {
+    pub fn new(factory: F, config: PoolConfig<A>) -> Result<Pool<A, F>, PoolError> {
+        if config.min_idle > config.max_size {
+            return Err(InvalidConfig(format!("min_idle ({}) is larger than max_size ({})",
+                                             config.min_idle, config.max_size)))
+        }
+        let pool = Pool {
+            inner: Arc::new(PoolInner {
+                factory: factory,
+                config: config,
+                state: Mutex::new(PoolState { idle: Vec::new(), size: 0 })
+            })
+        };
+        for _ in range(0, pool.inner.config.min_idle) {
+            let conn = try!(pool.connect());
+            let mut state = pool.inner.state.lock();
+            state.idle.push(conn);
+            state.size += 1;
+        }
+        Ok(pool)
+    }
+
+    // Takes an idle connection, or opens one if the pool is below its
+    // maximum size. Otherwise waits for a connection to be returned.
+    pub fn get(&self) -> Result<PooledConnection<A, F>, PoolError> {
+        let deadline = time::precise_time_ns() + self.inner.config.checkout_timeout_ms * 1000000;
+        let mut waking = false;
+        loop {
+            let checkout = {
+                let mut state = self.inner.state.lock();
+                let mut checkout = None;
+                while checkout.is_none() {
+                    checkout = match state.idle.pop() {
+                        Some(conn) => Some(Idle(conn)),
+                        None if state.size < self.inner.config.max_size => {
+                            state.size += 1;
+                            Some(Reserved)
+                        },
+                        None if time::precise_time_ns() >= deadline => Some(TimedOut),
+                        None => {
+                            if !waking {
+                                self.wake_at(deadline);
+                                waking = true;
+                            }
+                            state.cond.wait();
+                            None
+                        }
+                    };
+                }
+                checkout.unwrap()
+            };
+            let conn = match checkout {
+                Idle(conn) => {
+                    if !self.is_healthy(&conn) {
+                        self.discard();
+                        continue;
+                    }
+                    conn
+                },
+                Reserved => match self.connect() {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        self.discard();
+                        return Err(e)
+                    }
+                },
+                TimedOut => return Err(CheckoutTimeout)
+            };
+            let guard = self.guard(conn);
+            self.replenish();
+            return Ok(guard)
+        }
+    }
+
+    pub fn size(&self) -> uint {
+        self.inner.state.lock().size
+    }
+
+    pub fn idle_count(&self) -> uint {
+        self.inner.state.lock().idle.len()
+    }
+
+    fn connect(&self) -> Result<A, PoolError> {
+        let conn = match self.inner.factory.connect() {
+            Ok(conn) => conn,
+            Err(msg) => return Err(ConnectError(msg))
+        };
+        match self.inner.config.init {
+            Some(ref init) => match init.init(&conn) {
+                Ok(()) => (),
+                Err(msg) => return Err(ConnectError(msg))
+            },
+            None => ()
+        }
+        Ok(conn)
+    }
+
+    // Condition variables can't wait with a timeout, so a task wakes the
+    // waiters at the deadline. It only holds a weak reference, so the pool
+    // is not kept open until then.
+    fn wake_at(&self, deadline: u64) {
+        let inner = self.inner.downgrade();
+        let now = time::precise_time_ns();
+        let delay_ms = if deadline > now { (deadline - now) / 1000000 + 1 } else { 1 };
+        spawn(proc() {
+            timer::sleep(delay_ms);
+            match inner.upgrade() {
+                Some(inner) => inner.state.lock().cond.broadcast(),
+                None => ()
+            }
+        });
+    }
+
+    // Opens connections until `min_idle` of them are idle. Failures are left
+    // for the next checkout to report.
+    fn replenish(&self) {
+        loop {
+            {
+                let mut state = self.inner.state.lock();
+                if state.idle.len() >= self.inner.config.min_idle || state.size >= self.inner.config.max_size {
+                    return
+                }
+                state.size += 1;
+            }
+            match self.connect() {
+                Ok(conn) => {
+                    let mut state = self.inner.state.lock();
+                    state.idle.push(conn);
+                    state.cond.signal();
+                },
+                Err(_) => return self.discard()
+            }
+        }
+    }
+
+    fn is_healthy(&self, conn: &A) -> bool {
+        match self.inner.config.health_check {
+            Some(ref query) => conn.try_execute(query.as_slice()).is_ok(),
+            None => true
+        }
+    }
+
+    fn guard(&self, conn: A) -> PooledConnection<A, F> {
+        PooledConnection {
+            pool: self.clone(),
+            conn: Some(conn)
+        }
+    }
+}
+
+impl<A: Send, F> Pool<A, F> {
+    // Forgets a connection which was closed or never opened.
+    fn discard(&self) {
+        let mut state = self.inner.state.lock();
+        state.size -= 1;
+        state.cond.signal();
+    }
+}
+
+impl<A, F> Clone for Pool<A, F> {
+    fn clone(&self) -> Pool<A, F> {
+        Pool { inner: self.inner.clone() }
+    }
+}
+
+// A connection checked out of a `Pool`. It derefs to the connection itself.
+pub struct PooledConnection<A, F> {
+    pool: Pool<A, F>,
+    // Only `None` while being dropped.
+    conn: Option<A>
+}
+
+impl<A, F> PooledConnection<A, F> {
+    fn conn<'r>(&'r self) -> &'r A {
+        self.conn.get_ref()
+    }
+}
+
+impl<A, F> Deref<A> for PooledConnection<A, F> {
+    fn deref<'r>(&'r self) -> &'r A {
+        self.conn()
+    }
+}
+
+// A connection dropped while its task fails may be in the middle of a
+// transaction, so it is closed rather than returned.
+#[unsafe_destructor]
+impl<A: Send, F> Drop for PooledConnection<A, F> {
+    fn drop(&mut self) {
+        match self.conn.take() {
+            Some(_) if task::failing() => self.pool.discard(),
+            Some(conn) => {
+                let mut state = self.pool.inner.state.lock();
+                state.idle.push(conn);
+                state.cond.signal();
+            },
+            None => ()
+        }
+    }
+}
+
+impl<A: SqlAdapter + Send, F: ConnectionFactory<A>> SqlAdapter for PooledConnection<A, F> {
+    fn dialect(&self) -> &'static SqlDialect {
+        self.conn().dialect()
+    }
+
+    fn schema_binding(&self, key: TypeId) -> Option<Option<String>> {
+        self.conn().schema_binding(key)
+    }
+
+    fn try_execute(&self, query: &str) -> Result<(), String> {
+        self.conn().try_execute(query)
+    }
+
+    fn execute_params(&self, query: &str, params: &[SqlValue]) {
+        self.conn().execute_params(query, params)
+    }
+
+    fn table_columns(&self, table: &str) -> Vec<ColumnInfo> {
+        self.conn().table_columns(table)
+    }
+
+    fn table_exists<T:Table>(&self) -> bool {
+        self.conn().table_exists::<T>()
+    }
+
+    fn create_table_if_not_exists<T:Table>(&self) {
+        self.conn().create_table_if_not_exists::<T>()
+    }
+
+    fn drop_table<T:Table>(&self) {
+        self.conn().drop_table::<T>()
+    }
+
+    fn drop_table_if_exists<T:Table>(&self) {
+        self.conn().drop_table_if_exists::<T>()
+    }
+
+    fn insert_many<'r, T:Table, Iter: Iterator<&'r T>>(&self, records: Iter) -> Result<(), SqlError> {
+        self.conn().insert_many(records)
+    }
+
+    unsafe fn select_table<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlTableIter<'r, T> {
+        self.conn().select_table(query, params)
+    }
+
+    unsafe fn select_columns<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlSelectIter<'r, T> {
+        self.conn().select_columns(query, params)
+    }
+}
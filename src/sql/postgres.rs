@@ -0,0 +1,713 @@
+// A PostgreSQL backend speaking the frontend/backend protocol (version 3.0)
+// directly over TCP. Values are exchanged in the text format, so the cursor
+// parses them back with `from_str`. Passwords go through SCRAM-SHA-256, MD5
+// or in the clear, whichever the server asks for.
+
+use serialize::base64;
+use serialize::base64::{ToBase64, FromBase64};
+use std::ascii::StrAsciiExt;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::f64;
+use std::io::{BufferedStream, IoError};
+use std::io::net::tcp::TcpStream;
+use std::rand;
+use std::str;
+
+use super::{Table, SqlValue, TextValue};
+use adapter;
+use adapter::{SqlAdapter, SqlAdapterCursor, SqlTableIter, SqlSelectIter, ColumnInfo, SqlError,
+              UniqueViolation, CheckViolation, SqlFailure, bind_params};
+use dialect;
+use dialect::SqlDialect;
+use digest;
+
+static PROTOCOL_VERSION: i32 = 196608;
+
+#[deriving(Clone, PartialEq, Show)]
+pub struct PgError {
+    pub code: String,
+    pub message: String,
+    pub detail: Option<String>,
+    pub table: Option<String>,
+    pub constraint: Option<String>
+}
+
+impl PgError {
+    fn new(code: &str, message: &str) -> PgError {
+        PgError {
+            code: code.to_string(),
+            message: message.to_string(),
+            detail: None,
+            table: None,
+            constraint: None
+        }
+    }
+}
+
+// Broken connections are reported with the SQLSTATE for `connection_failure`.
+fn io_error(err: IoError) -> PgError {
+    PgError::new("08006", err.to_str().as_slice())
+}
+
+pub type PgResult<T> = Result<T, PgError>;
+
+pub type PgRow = Vec<Option<String>>;
+
+pub struct PgConnection {
+    stream: RefCell<BufferedStream<TcpStream>>,
+    // The transaction status of the last ReadyForQuery, `I` when idle.
+    status: Cell<u8>
+}
+
+fn push_i16(buf: &mut Vec<u8>, value: i16) {
+    buf.push((value >> 8) as u8);
+    buf.push(value as u8);
+}
+
+fn push_i32(buf: &mut Vec<u8>, value: i32) {
+    for i in range(0i32, 4) {
+        buf.push((value >> (8 * (3 - i))) as u8);
+    }
+}
+
+fn push_cstr(buf: &mut Vec<u8>, value: &str) {
+    buf.push_all(value.as_bytes());
+    buf.push(0);
+}
+
+// Reads the fields of a message body received from the server.
+// Reads past the end of the message, of unterminated strings or of text
+// which is not UTF-8 return zeros and empty strings and mark the message as
+// malformed, which `check` reports once the fields are read.
+struct MessageReader<'r> {
+    buf: &'r [u8],
+    pos: uint,
+    malformed: bool
+}
+
+// `protocol_violation`
+fn malformed_message() -> PgError {
+    PgError::new("08P01", "malformed message from server")
+}
+
+impl<'r> MessageReader<'r> {
+    fn new(buf: &'r [u8]) -> MessageReader<'r> {
+        MessageReader { buf: buf, pos: 0, malformed: false }
+    }
+
+    fn check(&self) -> PgResult<()> {
+        if self.malformed {
+            Err(malformed_message())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read_bytes(&mut self, len: uint) -> &'r [u8] {
+        if len > self.buf.len() - self.pos {
+            self.malformed = true;
+            self.pos = self.buf.len();
+            return self.buf.slice_from(self.pos)
+        }
+        let bytes = self.buf.slice(self.pos, self.pos + len);
+        self.pos += len;
+        bytes
+    }
+
+    fn read_i16(&mut self) -> i16 {
+        let bytes = self.read_bytes(2);
+        bytes.iter().fold(0i16, |acc, &b| (acc << 8) | (b as i16))
+    }
+
+    fn read_i32(&mut self) -> i32 {
+        let bytes = self.read_bytes(4);
+        bytes.iter().fold(0i32, |acc, &b| (acc << 8) | (b as i32))
+    }
+
+    fn read_str(&mut self, len: uint) -> String {
+        match str::from_utf8(self.read_bytes(len)) {
+            Some(s) => s.to_string(),
+            None => {
+                self.malformed = true;
+                String::new()
+            }
+        }
+    }
+
+    fn read_cstr(&mut self) -> String {
+        match self.buf.slice_from(self.pos).iter().position(|&b| b == 0) {
+            Some(len) => {
+                let s = self.read_str(len);
+                self.pos += 1;
+                s
+            },
+            None => {
+                self.malformed = true;
+                self.pos = self.buf.len();
+                String::new()
+            }
+        }
+    }
+}
+
+fn parse_error(body: &[u8]) -> PgError {
+    let mut reader = MessageReader::new(body);
+    let mut err = PgError::new("", "");
+    loop {
+        let field = match reader.read_bytes(1).head() {
+            Some(&field) if field != 0 => field,
+            _ => break
+        };
+        let value = reader.read_cstr();
+        match field as char {
+            'C' => err.code = value,
+            'M' => err.message = value,
+            'D' => err.detail = Some(value),
+            't' => err.table = Some(value),
+            'n' => err.constraint = Some(value),
+            _ => ()
+        }
+    }
+    err
+}
+
+fn parse_data_row(body: &[u8]) -> PgResult<PgRow> {
+    let mut reader = MessageReader::new(body);
+    let count = reader.read_i16();
+    let row = range(0, count).map(|_| {
+        match reader.read_i32() {
+            -1 => None,
+            len if len < 0 => {
+                reader.malformed = true;
+                None
+            },
+            len => Some(reader.read_str(len as uint))
+        }
+    }).collect();
+    try!(reader.check());
+    Ok(row)
+}
+
+// The client side of SCRAM-SHA-256 (RFC 7677) without channel binding. The
+// server takes the user from the startup message, so the one sent is empty,
+// and the password is used as given, without SASLprep.
+struct Scram {
+    nonce: String,
+    client_first_bare: String,
+    // The salted password and the auth message, which the server's final
+    // message is checked against.
+    verifier: Option<(Vec<u8>, String)>
+}
+
+impl Scram {
+    fn new() -> Scram {
+        let nonce: Vec<u8> = range(0u, 18).map(|_| rand::random::<u8>()).collect();
+        let nonce = nonce.as_slice().to_base64(base64::STANDARD);
+        Scram {
+            client_first_bare: format!("n=,r={}", nonce),
+            nonce: nonce,
+            verifier: None
+        }
+    }
+
+    fn client_first(&self) -> String {
+        format!("n,,{}", self.client_first_bare)
+    }
+
+    fn client_final(&mut self, password: &str, server_first: &str) -> PgResult<String> {
+        let (mut nonce, mut salt, mut iterations) = (None, None, None);
+        for attr in server_first.split(',') {
+            let (name, value) = (attr.slice_to(cmp::min(2, attr.len())), attr.slice_from(cmp::min(2, attr.len())));
+            match name {
+                "r=" => nonce = Some(value),
+                "s=" => salt = value.from_base64().ok(),
+                "i=" => iterations = from_str::<uint>(value),
+                _ => ()
+            }
+        }
+        let (nonce, salt, iterations) = match (nonce, salt, iterations) {
+            (Some(nonce), Some(salt), Some(iterations)) if nonce.starts_with(self.nonce.as_slice()) =>
+                (nonce, salt, iterations),
+            _ => return Err(PgError::new("08P01", "invalid SCRAM-SHA-256 challenge from server"))
+        };
+        let salted = digest::pbkdf2_sha256(password.as_bytes(), salt.as_slice(), iterations);
+        let client_key = digest::hmac_sha256(salted.as_slice(), "Client Key".as_bytes());
+        let stored_key = digest::sha256(client_key.as_slice());
+        let without_proof = format!("c=biws,r={}", nonce);
+        let auth_message = format!("{},{},{}", self.client_first_bare, server_first, without_proof);
+        let signature = digest::hmac_sha256(stored_key.as_slice(), auth_message.as_bytes());
+        let proof: Vec<u8> = client_key.iter().zip(signature.iter()).map(|(a, b)| *a ^ *b).collect();
+        self.verifier = Some((salted, auth_message));
+        Ok(format!("{},p={}", without_proof, proof.as_slice().to_base64(base64::STANDARD)))
+    }
+
+    fn verify(&self, server_final: &str) -> bool {
+        match self.verifier {
+            Some((ref salted, ref auth_message)) if server_final.starts_with("v=") => {
+                let server_key = digest::hmac_sha256(salted.as_slice(), "Server Key".as_bytes());
+                let signature = digest::hmac_sha256(server_key.as_slice(), auth_message.as_bytes());
+                server_final.slice_from(2).from_base64().ok() == Some(signature)
+            },
+            _ => false
+        }
+    }
+}
+
+pub fn connect(host: &str, port: u16, user: &str, password: Option<&str>, database: &str)
+        -> PgResult<PgConnection> {
+    let stream = match TcpStream::connect(host, port) {
+        Ok(stream) => stream,
+        Err(e) => return Err(io_error(e))
+    };
+    let conn = PgConnection {
+        stream: RefCell::new(BufferedStream::new(stream)),
+        status: Cell::new('I' as u8)
+    };
+
+    let mut startup = Vec::new();
+    push_i32(&mut startup, PROTOCOL_VERSION);
+    push_cstr(&mut startup, "user");
+    push_cstr(&mut startup, user);
+    push_cstr(&mut startup, "database");
+    push_cstr(&mut startup, database);
+    startup.push(0);
+    try!(conn.send(None, startup.as_slice()));
+    try!(conn.flush());
+
+    let mut scram = None;
+    loop {
+        let (tag, body) = try!(conn.receive());
+        match tag as char {
+            'R' => {
+                let mut reader = MessageReader::new(body.as_slice());
+                let method = reader.read_i32();
+                try!(reader.check());
+                let data = str::from_utf8(body.slice_from(4)).unwrap_or("");
+                let mut message = Vec::new();
+                match (method, password) {
+                    (0, _) => continue,
+                    (3, Some(password)) => push_cstr(&mut message, password),
+                    (5, Some(password)) => {
+                        let salt = reader.read_bytes(4);
+                        try!(reader.check());
+                        let inner = digest::to_hex(digest::md5(format!("{}{}", password, user).as_bytes()).as_slice());
+                        let mut salted = Vec::from_slice(inner.as_bytes());
+                        salted.push_all(salt);
+                        let response = format!("md5{}", digest::to_hex(digest::md5(salted.as_slice()).as_slice()));
+                        push_cstr(&mut message, response.as_slice());
+                    },
+                    // SASL, offering a list of mechanisms.
+                    (10, Some(_)) => {
+                        let mut mechanisms = Vec::new();
+                        loop {
+                            let mechanism = reader.read_cstr();
+                            if mechanism.is_empty() {
+                                break
+                            }
+                            mechanisms.push(mechanism);
+                        }
+                        try!(reader.check());
+                        if !mechanisms.contains(&"SCRAM-SHA-256".to_string()) {
+                            return Err(PgError::new("28000", format!("unsupported SASL mechanisms {}", mechanisms).as_slice()))
+                        }
+                        let client = Scram::new();
+                        let first = client.client_first();
+                        push_cstr(&mut message, "SCRAM-SHA-256");
+                        push_i32(&mut message, first.len() as i32);
+                        message.push_all(first.as_bytes());
+                        scram = Some(client);
+                    },
+                    (11, Some(password)) => match scram {
+                        Some(ref mut client) => message.push_all(try!(client.client_final(password, data)).as_bytes()),
+                        None => return Err(PgError::new("08P01", "unexpected SASL challenge from server"))
+                    },
+                    (12, _) => {
+                        if !scram.as_ref().map_or(false, |client| client.verify(data)) {
+                            return Err(PgError::new("28000", "the server failed to prove it knows the password"))
+                        }
+                        continue
+                    },
+                    (3, None) | (5, None) | (10, None) =>
+                        return Err(PgError::new("28P01", "the server requires a password")),
+                    (method, _) =>
+                        return Err(PgError::new("28000", format!("unsupported authentication method {}", method).as_slice()))
+                }
+                try!(conn.send(Some('p'), message.as_slice()));
+                try!(conn.flush());
+            },
+            'E' => return Err(parse_error(body.as_slice())),
+            'Z' => return Ok(conn),
+            _ => ()
+        }
+    }
+}
+
+impl PgConnection {
+    fn send(&self, tag: Option<char>, body: &[u8]) -> PgResult<()> {
+        let mut stream = self.stream.borrow_mut();
+        match tag {
+            Some(tag) => try!(stream.write_u8(tag as u8).map_err(io_error)),
+            None => ()
+        }
+        try!(stream.write_be_i32((body.len() + 4) as i32).map_err(io_error));
+        stream.write(body).map_err(io_error)
+    }
+
+    fn flush(&self) -> PgResult<()> {
+        self.stream.borrow_mut().flush().map_err(io_error)
+    }
+
+    fn receive(&self) -> PgResult<(u8, Vec<u8>)> {
+        let mut stream = self.stream.borrow_mut();
+        let tag = try!(stream.read_u8().map_err(io_error));
+        let len = try!(stream.read_be_i32().map_err(io_error));
+        // The length counts itself.
+        if len < 4 {
+            return Err(malformed_message())
+        }
+        let body = try!(stream.read_exact((len - 4) as uint).map_err(io_error));
+        Ok((tag, body))
+    }
+
+    // Reads messages up to ReadyForQuery, keeping the data rows and the
+    // first error reported.
+    fn receive_rows(&self) -> PgResult<Vec<PgRow>> {
+        let mut rows = Vec::new();
+        let mut error = None;
+        loop {
+            let (tag, body) = try!(self.receive());
+            match tag as char {
+                'D' => match parse_data_row(body.as_slice()) {
+                    Ok(row) => rows.push(row),
+                    Err(err) => if error.is_none() { error = Some(err) }
+                },
+                'E' => if error.is_none() { error = Some(parse_error(body.as_slice())) },
+                'Z' => {
+                    self.status.set(body.as_slice().get(0).map_or('I' as u8, |status| *status));
+                    break
+                },
+                _ => ()
+            }
+        }
+        match error {
+            Some(err) => Err(err),
+            None => Ok(rows)
+        }
+    }
+
+    // Runs one or more statements without parameters.
+    pub fn simple_query(&self, query: &str) -> PgResult<()> {
+        let mut message = Vec::new();
+        push_cstr(&mut message, query);
+        try!(self.send(Some('Q'), message.as_slice()));
+        try!(self.flush());
+        self.receive_rows().map(|_| ())
+    }
+
+    // Runs a single statement with `$n` placeholders through the extended
+    // query protocol.
+    pub fn query(&self, query: &str, params: &[Option<String>]) -> PgResult<Vec<PgRow>> {
+        let mut parse = Vec::new();
+        push_cstr(&mut parse, "");
+        push_cstr(&mut parse, query);
+        push_i16(&mut parse, 0);
+        try!(self.send(Some('P'), parse.as_slice()));
+
+        let mut bind = Vec::new();
+        push_cstr(&mut bind, "");
+        push_cstr(&mut bind, "");
+        push_i16(&mut bind, 0);
+        push_i16(&mut bind, params.len() as i16);
+        for param in params.iter() {
+            match *param {
+                Some(ref value) => {
+                    push_i32(&mut bind, value.len() as i32);
+                    bind.push_all(value.as_bytes());
+                },
+                None => push_i32(&mut bind, -1)
+            }
+        }
+        push_i16(&mut bind, 0);
+        try!(self.send(Some('B'), bind.as_slice()));
+
+        let mut execute = Vec::new();
+        push_cstr(&mut execute, "");
+        push_i32(&mut execute, 0);
+        try!(self.send(Some('E'), execute.as_slice()));
+        try!(self.send(Some('S'), []));
+        try!(self.flush());
+
+        self.receive_rows()
+    }
+
+    fn run(&self, query: &str, params: &[SqlValue]) -> PgResult<Vec<PgRow>> {
+        let cursor = PgCursor::new(Vec::new());
+        bind_params(&cursor, params);
+        self.query(numbered_placeholders(query).as_slice(), cursor.params.borrow().as_slice())
+    }
+
+    fn run_or_fail(&self, query: &str, params: &[SqlValue]) -> Vec<PgRow> {
+        match self.run(query, params) {
+            Ok(rows) => rows,
+            Err(e) => fail!("{}", e.message)
+        }
+    }
+}
+
+// Rewrites the `?` placeholders of queries built by `sql!` and `Query` to the
+// numbered `$n` form, leaving quoted strings and identifiers alone.
+pub fn numbered_placeholders(query: &str) -> String {
+    let mut result = String::with_capacity(query.len());
+    let mut quote = None;
+    let mut n = 0u;
+    for c in query.chars() {
+        match (quote, c) {
+            (None, '?') => {
+                n += 1;
+                result.push_str(format!("${}", n).as_slice());
+                continue;
+            },
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            _ => ()
+        }
+        result.push_char(c);
+    }
+    result
+}
+
+fn sql_typename(data_type: &str) -> String {
+    match data_type {
+        "bigint" | "integer" | "smallint" => "int".to_string(),
+        "double precision" | "real" => "real".to_string(),
+        "text" | "character varying" | "character" => "text".to_string(),
+        _ => data_type.to_string()
+    }
+}
+
+// Identifiers are quoted, so that columns named after keywords like `user`
+// work, and lower-cased, so that they match the unquoted ones of `sql!`
+// queries, which PostgreSQL folds to lower case.
+pub struct PostgresDialect;
+
+static POSTGRES_DIALECT: PostgresDialect = PostgresDialect;
+
+impl SqlDialect for PostgresDialect {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn typename(&self, typename: &str) -> String {
+        match typename {
+            // `int` is 64 bits wide on the platforms we support
+            "int" => "bigint".to_string(),
+            "real" => "double precision".to_string(),
+            _ => typename.to_string()
+        }
+    }
+
+    fn quote_identifier(&self, name: &str) -> String {
+        format!("\"{}\"", name.to_ascii_lower().replace("\"", "\"\""))
+    }
+
+    fn placeholder(&self, idx: uint) -> String {
+        format!("${}", idx)
+    }
+}
+
+// Extracts `a, b` from the detail `Key (a, b)=(1, 2) already exists.`
+fn unique_columns(detail: &Option<String>) -> Vec<String> {
+    let detail = match *detail {
+        Some(ref detail) => detail.as_slice(),
+        None => return Vec::new()
+    };
+    match (detail.find_str("Key ("), detail.find_str(")=")) {
+        (Some(start), Some(end)) if start + 5 <= end =>
+            detail.slice(start + 5, end).split_str(", ").map(|col| col.to_string()).collect(),
+        _ => Vec::new()
+    }
+}
+
+fn constraint_error(table: &str, err: PgError) -> SqlError {
+    match err.code.as_slice() {
+        "23505" => UniqueViolation {
+            table: table.to_string(),
+            columns: unique_columns(&err.detail)
+        },
+        "23514" => CheckViolation {
+            table: table.to_string(),
+            constraint: err.constraint.unwrap_or(String::new())
+        },
+        _ => SqlFailure(err.message)
+    }
+}
+
+pub struct PgCursor {
+    params: RefCell<Vec<Option<String>>>,
+    rows: Vec<PgRow>,
+    fetched: Cell<uint>
+}
+
+impl PgCursor {
+    fn new(rows: Vec<PgRow>) -> PgCursor {
+        PgCursor {
+            params: RefCell::new(Vec::new()),
+            rows: rows,
+            fetched: Cell::new(0)
+        }
+    }
+
+    fn bind(&self, idx: int, value: Option<String>) {
+        let mut params = self.params.borrow_mut();
+        while params.len() < idx as uint {
+            params.push(None);
+        }
+        *params.get_mut(idx as uint - 1) = value;
+    }
+
+    fn value<'r>(&'r self, idx: int) -> &'r str {
+        match *self.rows.get(self.fetched.get() - 1).get(idx as uint) {
+            Some(ref value) => value.as_slice(),
+            None => fail!("unexpected NULL at column {}", idx)
+        }
+    }
+}
+
+impl SqlAdapterCursor for PgCursor {
+    fn bind_null(&self, idx: int) {
+        self.bind(idx, None)
+    }
+
+    fn bind_int(&self, idx: int, value: int) {
+        self.bind(idx, Some(value.to_str()))
+    }
+
+    fn bind_str(&self, idx: int, value: &str) {
+        self.bind(idx, Some(value.to_string()))
+    }
+
+    fn bind_f64(&self, idx: int, value: f64) {
+        self.bind(idx, Some(f64::to_str_digits(value, 17)))
+    }
+
+    fn is_null(&self, idx: int) -> bool {
+        self.rows.get(self.fetched.get() - 1).get(idx as uint).is_none()
+    }
+
+    fn get_prim_int(&self, idx: int) -> int {
+        match from_str(self.value(idx)) {
+            Some(value) => value,
+            None => fail!("unexpected value `{}` at column {}", self.value(idx), idx)
+        }
+    }
+
+    fn get_prim_str(&self, idx: int) -> String {
+        self.value(idx).to_string()
+    }
+
+    fn get_prim_f64(&self, idx: int) -> f64 {
+        match from_str(self.value(idx)) {
+            Some(value) => value,
+            None => fail!("unexpected value `{}` at column {}", self.value(idx), idx)
+        }
+    }
+
+    fn fetch_row(&self) -> bool {
+        if self.fetched.get() < self.rows.len() {
+            self.fetched.set(self.fetched.get() + 1);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl SqlAdapter for PgConnection {
+    fn dialect(&self) -> &'static SqlDialect {
+        &POSTGRES_DIALECT as &'static SqlDialect
+    }
+
+    fn try_execute(&self, query: &str) -> Result<(), String> {
+        match self.simple_query(query) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.message)
+        }
+    }
+
+    fn execute_params(&self, query: &str, params: &[SqlValue]) {
+        self.run_or_fail(query, params);
+    }
+
+    // Unquoted identifiers are folded to lower case by PostgreSQL.
+    fn table_columns(&self, table: &str) -> Vec<ColumnInfo> {
+        let table = TextValue(table.to_ascii_lower());
+        let keys = self.run_or_fail(
+            "SELECT kcu.column_name FROM information_schema.table_constraints tc \
+             JOIN information_schema.key_column_usage kcu \
+             ON tc.constraint_name = kcu.constraint_name AND tc.table_name = kcu.table_name \
+             WHERE tc.constraint_type = 'PRIMARY KEY' AND tc.table_name = ?;",
+            [table.clone()]);
+        let rows = self.run_or_fail(
+            "SELECT column_name, data_type, is_nullable, column_default \
+             FROM information_schema.columns \
+             WHERE table_schema = current_schema() AND table_name = ? \
+             ORDER BY ordinal_position;",
+            [table]);
+        rows.move_iter().map(|row| {
+            let name = row.get(0).clone().unwrap();
+            ColumnInfo {
+                primary_key: keys.iter().any(|key| key.get(0).as_ref() == Some(&name)),
+                name: name,
+                typename: sql_typename(row.get(1).as_ref().unwrap().as_slice()),
+                notnull: row.get(2).as_ref().map(|s| s.as_slice()) == Some("NO"),
+                default: row.get(3).clone()
+            }
+        }).collect()
+    }
+
+    fn table_exists<T:Table>(&self) -> bool {
+        let rows = self.run_or_fail(
+            "SELECT count(*) FROM information_schema.tables \
+             WHERE table_schema = current_schema() AND table_name = ?;",
+            [TextValue(super::table_name::<T>().to_ascii_lower())]);
+        match rows.as_slice().head() {
+            Some(row) => row.get(0).as_ref().map(|s| s.as_slice()) != Some("0"),
+            None => false
+        }
+    }
+
+    fn create_table_if_not_exists<T:Table>(&self) {
+        self.execute(dialect::create_table_query::<T>(self.dialect(), self.schema::<T>()).as_slice());
+        for query in dialect::create_index_queries::<T>(self.dialect(), self.schema::<T>()).iter() {
+            self.execute(query.as_slice());
+        }
+    }
+
+    fn insert_many<'r, T:Table, Iter: Iterator<&'r T>>(&self, records: Iter) -> Result<(), SqlError> {
+        let query = dialect::insert_query::<T>(self.dialect(), self.schema::<T>());
+        let mut iter = records;
+        // Savepoints are an error outside a transaction block.
+        adapter::with_savepoint(self, self.status.get() == 'I' as u8, || {
+            for record in iter.by_ref() {
+                let cursor = PgCursor::new(Vec::new());
+                record.bind(&cursor);
+                match self.query(query.as_slice(), cursor.params.borrow().as_slice()) {
+                    Ok(_) => (),
+                    Err(e) => return Err(constraint_error(super::table_name::<T>(), e))
+                }
+            }
+            Ok(())
+        })
+    }
+
+    unsafe fn select_table<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlTableIter<'r, T> {
+        let rows = self.run_or_fail(query, params);
+        SqlTableIter::new(self, box PgCursor::new(rows))
+    }
+
+    unsafe fn select_columns<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlSelectIter<'r, T> {
+        let rows = self.run_or_fail(query, params);
+        SqlSelectIter::new(self, box PgCursor::new(rows))
+    }
+}
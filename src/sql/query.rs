@@ -0,0 +1,134 @@
+use adapter;
+use selector::QueryBuf;
+use super::{Table, SqlType, SqlValue, NullValue};
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum FilterOp {
+    OpEq,
+    OpNe,
+    OpLt,
+    OpLe,
+    OpGt,
+    OpGe
+}
+
+impl FilterOp {
+    fn to_sql(&self) -> &'static str {
+        match *self {
+            OpEq => "=",
+            OpNe => "!=",
+            OpLt => "<",
+            OpLe => "<=",
+            OpGt => ">",
+            OpGe => ">="
+        }
+    }
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum Order {
+    Asc,
+    Desc
+}
+
+// A `SELECT * FROM` query over a table, composed at runtime.
+// Column names cannot be checked at compile time, so the first one which is
+// not a column of the table, or a comparison the query cannot make, is kept
+// and returned by `build` and `fetch`.
+pub struct Query<T> {
+    filters: Vec<(String, FilterOp, SqlValue)>,
+    order: Vec<(String, Order)>,
+    limit: Option<uint>,
+    error: Option<String>
+}
+
+impl<T:Table> Query<T> {
+    pub fn new() -> Query<T> {
+        Query {
+            filters: Vec::new(),
+            order: Vec::new(),
+            limit: None,
+            error: None
+        }
+    }
+
+    // A `None` compares with `IS NULL` or `IS NOT NULL`, as `= NULL` matches
+    // no row, and cannot be ordered against.
+    pub fn filter<V: SqlType>(mut self, column: &str, op: FilterOp, value: &V) -> Query<T> {
+        self.check_column(column);
+        let value = value.value();
+        match (op, &value) {
+            (OpEq, _) | (OpNe, _) => (),
+            (_, &NullValue) =>
+                self.fail(format!("cannot compare column `{}` with NULL using {}", column, op.to_sql())),
+            _ => ()
+        }
+        self.filters.push((column.to_string(), op, value));
+        self
+    }
+
+    pub fn order_by(mut self, column: &str, order: Order) -> Query<T> {
+        self.check_column(column);
+        self.order.push((column.to_string(), order));
+        self
+    }
+
+    pub fn limit(mut self, limit: uint) -> Query<T> {
+        self.limit = Some(limit);
+        self
+    }
+
+    fn check_column(&mut self, column: &str) {
+        if !super::columns::<T>().iter().any(|c| c.name == column) {
+            self.fail(format!("`{}` is not a column of table `{}`", column, super::table_name::<T>()))
+        }
+    }
+
+    fn fail(&mut self, msg: String) {
+        if self.error.is_none() {
+            self.error = Some(msg)
+        }
+    }
+
+    pub fn build(&self) -> Result<QueryBuf, String> {
+        match self.error {
+            Some(ref msg) => return Err(msg.clone()),
+            None => ()
+        }
+
+        let mut buf = QueryBuf::new();
+        buf.push_str("SELECT * FROM ");
+        buf.push_table::<T>();
+
+        for (i, &(ref column, op, ref value)) in self.filters.iter().enumerate() {
+            buf.push_str(if i == 0 { " WHERE " } else { " AND " });
+            match (op, value) {
+                (OpEq, &NullValue) => buf.push_str(format!("{} IS NULL", column).as_slice()),
+                (OpNe, &NullValue) => buf.push_str(format!("{} IS NOT NULL", column).as_slice()),
+                _ => {
+                    buf.push_str(format!("{} {} ", column, op.to_sql()).as_slice());
+                    buf.push_value(value.clone());
+                }
+            }
+        }
+
+        for (i, &(ref column, order)) in self.order.iter().enumerate() {
+            buf.push_str(if i == 0 { " ORDER BY " } else { ", " });
+            buf.push_str(column.as_slice());
+            buf.push_str(match order { Asc => " ASC", Desc => " DESC" });
+        }
+
+        match self.limit {
+            Some(limit) => buf.push_str(format!(" LIMIT {}", limit).as_slice()),
+            None => ()
+        }
+
+        buf.push_str(";");
+        Ok(buf)
+    }
+
+    pub fn fetch<'r, A: adapter::SqlAdapter>(&self, db: &'r A) -> Result<adapter::SqlTableIter<'r, T>, String> {
+        let buf = try!(self.build());
+        Ok(unsafe { db.select_table(buf.query_on(db).as_slice(), buf.params()) })
+    }
+}
@@ -1,11 +1,109 @@
+use std::intrinsics::TypeId;
+
 use adapter;
-use super::{Table, SqlType};
+use super::{Table, SqlPrimitive, SqlType, SqlValue, NullValue, SqlComparable, SqlText, SqlArith, SqlDivide, SqlConcat};
+
+#[deriving(Clone)]
+pub struct QueryBuf {
+    query: String,
+    params: Vec<SqlValue>,
+    // The tables named in `query`: where and how long the name is, the type
+    // and the unqualified name, so a connection can bind them elsewhere.
+    tables: Vec<(uint, uint, TypeId, String)>
+}
+
+impl QueryBuf {
+    pub fn new() -> QueryBuf {
+        QueryBuf {
+            query: String::new(),
+            params: Vec::new(),
+            tables: Vec::new()
+        }
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        self.query.push_str(s)
+    }
+
+    // Names the table of `T`, qualified with its `#[sql_schema]` until a
+    // connection binds it elsewhere.
+    pub fn push_table<T: Table>(&mut self) {
+        let name = super::qualified_table_name::<T>();
+        self.tables.push((self.query.len(), name.len(), super::type_key::<T>(),
+                          super::table_name::<T>().to_string()));
+        self.query.push_str(name.as_slice())
+    }
+
+    pub fn push_param<T: SqlType>(&mut self, value: &T) {
+        self.push_value(value.value())
+    }
+
+    pub fn push_value(&mut self, value: SqlValue) {
+        self.query.push_str("?");
+        self.params.push(value)
+    }
+
+    // `column op ?`, where a NULL compares with `IS NULL` or `IS NOT NULL`,
+    // as `= NULL` matches no row.
+    pub fn push_comparison<T: SqlType>(&mut self, column: &str, op: &str, value: &T) {
+        match (op, value.value()) {
+            ("=", NullValue) => self.query.push_str(format!("{} IS NULL", column).as_slice()),
+            ("!=", NullValue) => self.query.push_str(format!("{} IS NOT NULL", column).as_slice()),
+            (_, value) => {
+                self.query.push_str(format!("{} {} ", column, op).as_slice());
+                self.push_value(value)
+            }
+        }
+    }
+
+    // `column IN (?, ...)`, or a predicate no row matches when `values` is
+    // empty, as only SQLite accepts `IN ()`.
+    pub fn push_in_list<T: SqlType>(&mut self, column: &str, values: &[T]) {
+        if values.is_empty() {
+            return self.query.push_str("1 = 0")
+        }
+        self.query.push_str(format!("{} IN (", column).as_slice());
+        for (i, value) in values.iter().enumerate() {
+            if i != 0 {
+                self.query.push_str(", ");
+            }
+            self.push_param(value);
+        }
+        self.query.push_str(")")
+    }
+
+    pub fn query<'r>(&'r self) -> &'r str {
+        self.query.as_slice()
+    }
+
+    pub fn params<'r>(&'r self) -> &'r [SqlValue] {
+        self.params.as_slice()
+    }
+
+    // The query with its tables in the schemas `db` binds them to.
+    pub fn query_on<A: adapter::SqlAdapter>(&self, db: &A) -> String {
+        let mut query = String::new();
+        let mut pos = 0;
+        for &(start, len, key, ref table) in self.tables.iter() {
+            query.push_str(self.query.as_slice().slice(pos, start));
+            match db.schema_binding(key) {
+                Some(schema) =>
+                    query.push_str(super::qualified_name(schema.as_ref().map(|schema| schema.as_slice()),
+                                                         table.as_slice()).as_slice()),
+                None => query.push_str(self.query.as_slice().slice(start, start + len))
+            }
+            pos = start + len;
+        }
+        query.push_str(self.query.as_slice().slice_from(pos));
+        query
+    }
+}
 
 pub struct TableSelector<T> {
-    _query: &'static str
+    _query: QueryBuf
 }
 
-pub fn table_selector<T>(query: &'static str) -> TableSelector<T> {
+pub fn table_selector<T>(query: QueryBuf) -> TableSelector<T> {
     TableSelector {
         _query: query
     }
@@ -13,19 +111,27 @@ pub fn table_selector<T>(query: &'static str) -> TableSelector<T> {
 
 impl<T:Table> TableSelector<T> {
     pub fn query<'r>(&'r self) -> &'r str {
-        self._query
+        self._query.query()
+    }
+
+    pub fn params<'r>(&'r self) -> &'r [SqlValue] {
+        self._query.params()
+    }
+
+    pub fn buf<'r>(&'r self) -> &'r QueryBuf {
+        &self._query
     }
 
     pub fn fetch<'r, A: adapter::SqlAdapter>(&self, db: &'r A) -> adapter::SqlTableIter<'r, T> {
-        unsafe { db.select_table(self._query) }
+        unsafe { db.select_table(self._query.query_on(db).as_slice(), self._query.params()) }
     }
 }
 
 pub struct ColumnSelector<T> {
-    _query: &'static str
+    _query: QueryBuf
 }
 
-pub fn column_selector<T:ColumnFacade>(query: &'static str, _: Option<T>) -> ColumnSelector<T> {
+pub fn column_selector<T:ColumnFacade>(query: QueryBuf, _: Option<T>) -> ColumnSelector<T> {
     ColumnSelector {
         _query: query
     }
@@ -33,26 +139,107 @@ pub fn column_selector<T:ColumnFacade>(query: &'static str, _: Option<T>) -> Col
 
 impl<T:ColumnFacade> ColumnSelector<T> {
     pub fn query<'r>(&'r self) -> &'r str {
-        self._query
+        self._query.query()
+    }
+
+    pub fn params<'r>(&'r self) -> &'r [SqlValue] {
+        self._query.params()
+    }
+
+    pub fn buf<'r>(&'r self) -> &'r QueryBuf {
+        &self._query
     }
 
     pub fn fetch<'r, A: adapter::SqlAdapter>(&self, db: &'r A) -> adapter::SqlSelectIter<'r, T> {
-        unsafe { db.select_columns(self._query) }
+        unsafe { db.select_columns(self._query.query_on(db).as_slice(), self._query.params()) }
     }
 }
 
+pub struct RowSelector<T> {
+    _query: QueryBuf,
+    _get_row: fn(&adapter::SqlAdapterCursor) -> T
+}
+
+pub fn row_selector<T>(query: QueryBuf, get_row: fn(&adapter::SqlAdapterCursor) -> T) -> RowSelector<T> {
+    RowSelector {
+        _query: query,
+        _get_row: get_row
+    }
+}
+
+impl<T> RowSelector<T> {
+    pub fn query<'r>(&'r self) -> &'r str {
+        self._query.query()
+    }
+
+    pub fn params<'r>(&'r self) -> &'r [SqlValue] {
+        self._query.params()
+    }
+
+    pub fn fetch<'r, A: adapter::SqlAdapter>(&self, db: &'r A) -> adapter::SqlRowIter<'r, T> {
+        let iter: adapter::SqlSelectIter<'r, ()> =
+            unsafe { db.select_columns(self._query.query_on(db).as_slice(), self._query.params()) };
+        iter.map_rows(self._get_row)
+    }
+}
+
+// Stand-ins for the value of a select list item. `sql!` only uses them to
+// infer column types, they are never called.
+
+pub fn column_value<T: SqlType>(_: &T) -> T {
+    fail!("column_value is only used for type inference")
+}
+
+pub fn arith_value<L: SqlArith<R, O>, R, O>(_: &L, _: &R) -> O {
+    fail!("arith_value is only used for type inference")
+}
+
+pub fn divide_value<L: SqlDivide<R, O>, R, O>(_: &L, _: &R) -> O {
+    fail!("divide_value is only used for type inference")
+}
+
+pub fn concat_value<L: SqlConcat<R, O>, R, O>(_: &L, _: &R) -> O {
+    fail!("concat_value is only used for type inference")
+}
+
+// Type checks emitted by `sql!` for WHERE clauses. They are never called at
+// runtime, only their signatures matter.
+
+pub fn check_param<C, P: SqlComparable<C> + SqlType>(_: &C, _: &P) {}
+
+pub fn check_ordered_param<C, P: SqlComparable<C> + SqlPrimitive + SqlType>(_: &C, _: &P) {}
+
+pub fn check_in<C, P: SqlComparable<C> + SqlType>(_: &C, _: &[P]) {}
+
+pub fn check_column<C, D: SqlComparable<C>>(_: &C, _: &D) {}
+
+pub fn check_nullable<T: SqlPrimitive>(_: &Option<T>) {}
+
+pub fn check_pattern<C: SqlText, P: SqlText + SqlType>(_: &C, _: &P) {}
+
 pub trait ColumnFacade {
     fn get(cursor: &adapter::SqlAdapterCursor) -> Self;
+    fn values(&self) -> Vec<SqlValue>;
 }
 
 impl<A:SqlType> ColumnFacade for (A,) {
     fn get(cursor: &adapter::SqlAdapterCursor) -> (A,) {
         (SqlType::get_col(cursor, 0), )
     }
+
+    fn values(&self) -> Vec<SqlValue> {
+        let (ref a,) = *self;
+        vec![a.value()]
+    }
 }
 
 impl<A:SqlType, B:SqlType> ColumnFacade for (A, B) {
     fn get(cursor: &adapter::SqlAdapterCursor) -> (A, B) {
         (SqlType::get_col(cursor, 0), SqlType::get_col(cursor, 1))
     }
+
+    fn values(&self) -> Vec<SqlValue> {
+        let (ref a, ref b) = *self;
+        vec![a.value(), b.value()]
+    }
 }
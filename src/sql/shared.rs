@@ -0,0 +1,166 @@
+// A connection owned by a task of its own. `SharedDatabase` is a handle that
+// can be cloned and sent to other tasks, each request running on the owning
+// task and its result sent back.
+//
+// Rows travel between the tasks as `SqlValue`s, encoded and decoded by
+// functions instantiated for the requested types. A request that fails the
+// task is answered with an error and takes the connection with it. Another
+// connection would have lost the in-memory databases, attachments, pragmas and
+// open transaction of the first, so none is opened and every later request
+// fails as well.
+
+use std::task;
+
+use super::{Table, SqlValue};
+use adapter::{SqlAdapter, SqlAdapterCursor, SqlTableIter, SqlSelectIter, SqlError, SqlFailure, ValueCursor};
+use pool::ConnectionFactory;
+use selector::{QueryBuf, TableSelector, ColumnSelector, ColumnFacade};
+
+type Rows = Vec<Vec<SqlValue>>;
+
+enum Request<A> {
+    Execute(String, Sender<Result<(), String>>),
+    CreateTable(fn(&A), Sender<()>),
+    Insert(Rows, fn(&A, Rows) -> Result<(), SqlError>, Sender<Result<(), SqlError>>),
+    SelectAll(fn(&A) -> Rows, Sender<Rows>),
+    Select(QueryBuf, fn(&A, &QueryBuf) -> Rows, Sender<Rows>)
+}
+
+fn serve<A: SqlAdapter>(db: &A, request: Request<A>) {
+    match request {
+        Execute(query, tx) => tx.send(db.try_execute(query.as_slice())),
+        CreateTable(create, tx) => tx.send(create(db)),
+        Insert(rows, insert, tx) => tx.send(insert(db, rows)),
+        SelectAll(select, tx) => tx.send(select(db)),
+        Select(buf, select, tx) => tx.send(select(db, &buf))
+    }
+}
+
+fn encode<T: Table>(record: &T) -> Vec<SqlValue> {
+    let cursor = ValueCursor::new(Vec::new());
+    record.bind(&cursor);
+    cursor.params()
+}
+
+fn decode<T>(rows: Rows, get_row: fn(&SqlAdapterCursor) -> T) -> Vec<T> {
+    let cursor = ValueCursor::new(rows);
+    let mut records = Vec::new();
+    while cursor.fetch_row() {
+        records.push(get_row(&cursor));
+    }
+    records
+}
+
+fn create_table<A: SqlAdapter, T: Table>(db: &A) {
+    db.create_table_if_not_exists::<T>()
+}
+
+fn insert_rows<A: SqlAdapter, T: Table>(db: &A, rows: Rows) -> Result<(), SqlError> {
+    let records: Vec<T> = decode(rows, Table::get_row);
+    db.insert_many(records.iter())
+}
+
+fn select_all_rows<A: SqlAdapter, T: Table>(db: &A) -> Rows {
+    let iter: SqlTableIter<T> = db.select_all();
+    iter.map(|record| encode(&record)).collect()
+}
+
+fn select_table_rows<A: SqlAdapter, T: Table>(db: &A, buf: &QueryBuf) -> Rows {
+    let iter: SqlTableIter<T> = unsafe { db.select_table(buf.query_on(db).as_slice(), buf.params()) };
+    iter.map(|record| encode(&record)).collect()
+}
+
+fn select_column_rows<A: SqlAdapter, T: ColumnFacade>(db: &A, buf: &QueryBuf) -> Rows {
+    let iter: SqlSelectIter<T> = unsafe { db.select_columns(buf.query_on(db).as_slice(), buf.params()) };
+    iter.map(|row| row.values()).collect()
+}
+
+pub struct SharedDatabase<A> {
+    requests: Sender<Request<A>>
+}
+
+impl<A: SqlAdapter + Send> SharedDatabase<A> {
+    // Opens the connection with `factory` on a new task.
+    pub fn new<F: ConnectionFactory<A>>(factory: F) -> Result<SharedDatabase<A>, String> {
+        let (tx, rx) = channel::<Request<A>>();
+        let (opened_tx, opened_rx) = channel();
+        spawn(proc() {
+            let mut db = match factory.connect() {
+                Ok(db) => {
+                    opened_tx.send(Ok(()));
+                    db
+                },
+                Err(msg) => return opened_tx.send(Err(msg))
+            };
+            for request in rx.iter() {
+                // Ending the task closes the channel, which the senders of
+                // this and every later request see as a failure.
+                match task::try(proc() {
+                    serve(&db, request);
+                    db
+                }) {
+                    Ok(conn) => db = conn,
+                    Err(_) => break
+                }
+            }
+        });
+        try!(opened_rx.recv());
+        Ok(SharedDatabase { requests: tx })
+    }
+
+    // Sends `request` and waits for its answer on `rx`.
+    fn send<R: Send>(&self, request: Request<A>, rx: Receiver<R>) -> Result<R, String> {
+        match self.requests.send_opt(request) {
+            Ok(()) => rx.recv_opt().map_err(|_| "the request failed on the database task".to_string()),
+            Err(_) => Err("the database task ended after a failed request".to_string())
+        }
+    }
+
+    pub fn execute(&self, query: &str) -> Result<(), String> {
+        let (tx, rx) = channel();
+        let result = try!(self.send(Execute(query.to_string(), tx), rx));
+        result
+    }
+
+    pub fn create_table_if_not_exists<T: Table>(&self) -> Result<(), String> {
+        let (tx, rx) = channel();
+        self.send(CreateTable(create_table::<A, T>, tx), rx)
+    }
+
+    pub fn insert_many<'r, T: Table, Iter: Iterator<&'r T>>(&self, records: Iter) -> Result<(), SqlError> {
+        let mut iter = records;
+        let rows: Rows = iter.map(|record| encode(record)).collect();
+        let (tx, rx) = channel();
+        match self.send(Insert(rows, insert_rows::<A, T>, tx), rx) {
+            Ok(result) => result,
+            Err(msg) => Err(SqlFailure(msg))
+        }
+    }
+
+    pub fn select_all<T: Table>(&self) -> Result<Vec<T>, String> {
+        let (tx, rx) = channel();
+        let rows = try!(self.send(SelectAll(select_all_rows::<A, T>, tx), rx));
+        Ok(decode(rows, Table::get_row))
+    }
+
+    // Fetches every row of a `sql!` selector.
+    pub fn fetch<T: Table>(&self, selector: TableSelector<T>) -> Result<Vec<T>, String> {
+        let (tx, rx) = channel();
+        let request = Select(selector.buf().clone(), select_table_rows::<A, T>, tx);
+        let rows = try!(self.send(request, rx));
+        Ok(decode(rows, Table::get_row))
+    }
+
+    pub fn fetch_columns<T: ColumnFacade>(&self, selector: ColumnSelector<T>) -> Result<Vec<T>, String> {
+        let (tx, rx) = channel();
+        let request = Select(selector.buf().clone(), select_column_rows::<A, T>, tx);
+        let rows = try!(self.send(request, rx));
+        Ok(decode(rows, ColumnFacade::get))
+    }
+}
+
+impl<A> Clone for SharedDatabase<A> {
+    fn clone(&self) -> SharedDatabase<A> {
+        SharedDatabase { requests: self.requests.clone() }
+    }
+}
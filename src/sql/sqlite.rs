@@ -0,0 +1,461 @@
+use std::ascii::StrAsciiExt;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::intrinsics::TypeId;
+use std::io;
+use std::io::fs;
+use std::mem;
+use std::os;
+use std::rand;
+use sqlite3;
+
+use super::{Table, SqlValue};
+use adapter::{SqlAdapter, SqlAdapterCursor, SqlTableIter, SqlSelectIter, ColumnInfo, SqlError, SqlFailure};
+use adapter;
+use dialect;
+use dialect::SqlDialect;
+use pool::ConnectionFactory;
+use shared::SharedDatabase;
+
+// The number of prepared statements `open` keeps per connection.
+pub static DEFAULT_CACHE_CAPACITY: uint = 32;
+
+// Opens a database with foreign key constraints enforced, which SQLite
+// leaves off by default for every new connection, and a statement cache of
+// `DEFAULT_CACHE_CAPACITY`.
+pub fn open(path: &str) -> sqlite3::SqliteResult<CachedDatabase> {
+    open_with_capacity(path, DEFAULT_CACHE_CAPACITY)
+}
+
+pub fn open_with_capacity(path: &str, capacity: uint) -> sqlite3::SqliteResult<CachedDatabase> {
+    let db = try!(sqlite3::open(path));
+    match db.exec("PRAGMA foreign_keys = ON;") {
+        Ok(_) => Ok(CachedDatabase::new(db, capacity)),
+        Err(e) => Err(e)
+    }
+}
+
+pub fn open_in_memory() -> sqlite3::SqliteResult<CachedDatabase> {
+    open(":memory:")
+}
+
+// Attaches the database at `path` under `alias`, where the tables of types
+// declared with `#[sql_schema = "alias"]` live.
+pub trait Attach {
+    fn attach(&self, path: &str, alias: &str) -> Result<(), String>;
+    fn detach(&self, alias: &str) -> Result<(), String>;
+}
+
+// Aliases are pasted into the query, so only plain identifiers are accepted.
+fn check_alias(alias: &str) -> Result<(), String> {
+    let valid = match alias.chars().next() {
+        Some(c) => (c.is_alphabetic() || c == '_') && alias.chars().all(|c| c.is_alphanumeric() || c == '_'),
+        None => false
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid database alias `{}`", alias))
+    }
+}
+
+impl Attach for sqlite3::Database {
+    fn attach(&self, path: &str, alias: &str) -> Result<(), String> {
+        try!(check_alias(alias));
+        let query = format!("ATTACH DATABASE '{}' AS {};", path.replace("'", "''"),
+                            SqliteDialect.quote_identifier(alias));
+        self.try_execute(query.as_slice())
+    }
+
+    fn detach(&self, alias: &str) -> Result<(), String> {
+        try!(check_alias(alias));
+        self.try_execute(format!("DETACH DATABASE {};", SqliteDialect.quote_identifier(alias)).as_slice())
+    }
+}
+
+// Opens the database at `path` on a task of its own.
+pub fn open_shared(path: &str) -> Result<SharedDatabase<CachedDatabase>, String> {
+    SharedDatabase::new(SqliteFactory::new(path))
+}
+
+// Opens connections to the database at `path` for a `pool::Pool`.
+pub struct SqliteFactory {
+    path: String
+}
+
+impl SqliteFactory {
+    pub fn new(path: &str) -> SqliteFactory {
+        SqliteFactory { path: path.to_string() }
+    }
+}
+
+impl ConnectionFactory<CachedDatabase> for SqliteFactory {
+    fn connect(&self) -> Result<CachedDatabase, String> {
+        match open(self.path.as_slice()) {
+            Ok(db) => Ok(db),
+            Err(e) => Err(format!("cannot open {}: {}", self.path, e))
+        }
+    }
+}
+
+// A directory of its own under the temporary directory. Creating it fails if
+// it exists, so no other `TempDatabase` can claim it.
+fn temp_dir() -> sqlite3::SqliteResult<Path> {
+    loop {
+        let dir = os::tmpdir().join(format!("sql-{:016x}", rand::random::<u64>()));
+        match fs::mkdir(&dir, io::UserRWX) {
+            Ok(()) => return Ok(dir),
+            Err(ref e) if e.kind == io::PathAlreadyExists => (),
+            Err(_) => return Err(sqlite3::SQLITE_CANTOPEN)
+        }
+    }
+}
+
+// A database in a directory of its own under the temporary directory, which
+// is removed with its journal when dropped. It derefs to the `CachedDatabase`.
+pub struct TempDatabase {
+    // Only `None` while dropped, so the connection closes before the files
+    // are removed.
+    db: Option<CachedDatabase>,
+    dir: Path,
+    path: Path
+}
+
+impl TempDatabase {
+    pub fn new() -> sqlite3::SqliteResult<TempDatabase> {
+        let dir = try!(temp_dir());
+        let path = dir.join("db.sqlite3");
+        match open(path.as_str().unwrap()) {
+            Ok(db) => Ok(TempDatabase { db: Some(db), dir: dir, path: path }),
+            Err(e) => {
+                let _ = fs::rmdir_recursive(&dir);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn path<'r>(&'r self) -> &'r Path {
+        &self.path
+    }
+}
+
+impl Deref<CachedDatabase> for TempDatabase {
+    fn deref<'r>(&'r self) -> &'r CachedDatabase {
+        self.db.get_ref()
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        self.db.take();
+        let _ = fs::rmdir_recursive(&self.dir);
+    }
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub struct CacheStats {
+    pub hits: uint,
+    pub misses: uint,
+    pub evictions: uint
+}
+
+// A prepared statement of a `CachedDatabase`. Its cursor claims to borrow the
+// database for `'static`, which holds as long as statements are only made by
+// `Statement::prepare` from the boxed database of a `CachedDatabase`, never
+// leave it, and are finalized before it closes: the box keeps the database at
+// the same address, `CachedCursor` borrows the `CachedDatabase`, and its
+// destructor clears the statements first.
+struct Statement {
+    cursor: sqlite3::Cursor<'static>
+}
+
+impl Statement {
+    fn prepare(db: &Box<sqlite3::Database>, query: &str) -> Result<Statement, String> {
+        match db.prepare(query, &None) {
+            Err(_) => Err(db.get_errmsg()),
+            Ok(cursor) => Ok(Statement {
+                cursor: unsafe { mem::transmute::<sqlite3::Cursor, sqlite3::Cursor<'static>>(cursor) }
+            })
+        }
+    }
+}
+
+// The connection of the sqlite adapter, which keeps up to `capacity` prepared
+// statements, keyed by query text, and reuses them in `execute_params`,
+// `insert_many` and the selects. The least recently used statement is
+// finalized first. Tables may be bound to attached databases on it with
+// `set_schema`. It derefs to the `sqlite3::Database`, which is an adapter as
+// well but prepares every statement anew and knows no such bindings.
+pub struct CachedDatabase {
+    // Boxed so the cached cursors' borrow of it stays valid when moved.
+    db: Box<sqlite3::Database>,
+    capacity: uint,
+    // Most recently used last. Statements in use are taken out of the list.
+    statements: RefCell<Vec<(String, Statement)>>,
+    stats: RefCell<CacheStats>,
+    schemas: RefCell<HashMap<TypeId, Option<String>>>
+}
+
+impl CachedDatabase {
+    pub fn new(db: sqlite3::Database, capacity: uint) -> CachedDatabase {
+        CachedDatabase {
+            db: box db,
+            capacity: capacity,
+            statements: RefCell::new(Vec::new()),
+            stats: RefCell::new(CacheStats { hits: 0, misses: 0, evictions: 0 }),
+            schemas: RefCell::new(HashMap::new())
+        }
+    }
+
+    // Binds the table of `T` to the attached database `schema` on this
+    // connection, or to the main one with `None`, in place of its
+    // `#[sql_schema]`.
+    pub fn set_schema<T: Table>(&self, schema: Option<&str>) -> Result<(), String> {
+        match schema {
+            Some(schema) => try!(check_alias(schema)),
+            None => ()
+        }
+        self.schemas.borrow_mut().insert(super::type_key::<T>(), schema.map(|schema| schema.to_string()));
+        Ok(())
+    }
+
+    // Goes back to the `#[sql_schema]` of `T`.
+    pub fn reset_schema<T: Table>(&self) {
+        self.schemas.borrow_mut().remove(&super::type_key::<T>());
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.stats.borrow().clone()
+    }
+
+    fn checkout<'r>(&'r self, query: &str) -> CachedCursor<'r> {
+        match self.try_checkout(query) {
+            Ok(cursor) => cursor,
+            Err(msg) => fail!("{}", msg)
+        }
+    }
+
+    fn try_checkout<'r>(&'r self, query: &str) -> Result<CachedCursor<'r>, String> {
+        let cached = {
+            let mut statements = self.statements.borrow_mut();
+            match statements.iter().position(|&(ref cached, _)| cached.as_slice() == query) {
+                Some(idx) => statements.remove(idx).map(|(_, statement)| statement),
+                None => None
+            }
+        };
+        let statement = match cached {
+            Some(statement) => {
+                self.stats.borrow_mut().hits += 1;
+                statement
+            },
+            None => {
+                self.stats.borrow_mut().misses += 1;
+                try!(Statement::prepare(&self.db, query))
+            }
+        };
+        Ok(CachedCursor {
+            db: self,
+            query: query.to_string(),
+            statement: Some(statement)
+        })
+    }
+
+    fn checkin(&self, query: String, statement: Statement) {
+        statement.cursor.reset();
+        let mut statements = self.statements.borrow_mut();
+        // The same query may have been prepared again while this one was in use.
+        if statements.iter().any(|&(ref cached, _)| *cached == query) {
+            return;
+        }
+        statements.push((query, statement));
+        if statements.len() > self.capacity {
+            statements.remove(0);
+            self.stats.borrow_mut().evictions += 1;
+        }
+    }
+}
+
+impl Deref<sqlite3::Database> for CachedDatabase {
+    fn deref<'r>(&'r self) -> &'r sqlite3::Database {
+        &*self.db
+    }
+}
+
+impl Drop for CachedDatabase {
+    fn drop(&mut self) {
+        // Statements must be finalized before the database is closed.
+        self.statements.borrow_mut().clear();
+    }
+}
+
+// A statement checked out of a `CachedDatabase`, put back when dropped.
+struct CachedCursor<'r> {
+    db: &'r CachedDatabase,
+    query: String,
+    statement: Option<Statement>
+}
+
+impl<'r> CachedCursor<'r> {
+    fn cursor<'a>(&'a self) -> &'a sqlite3::Cursor<'static> {
+        &self.statement.get_ref().cursor
+    }
+}
+
+#[unsafe_destructor]
+impl<'r> Drop for CachedCursor<'r> {
+    fn drop(&mut self) {
+        match self.statement.take() {
+            Some(statement) => self.db.checkin(self.query.clone(), statement),
+            None => ()
+        }
+    }
+}
+
+impl<'r> SqlAdapterCursor for CachedCursor<'r> {
+    fn bind_null(&self, idx: int) {
+        self.cursor().bind_null(idx)
+    }
+
+    fn bind_int(&self, idx: int, value: int) {
+        self.cursor().bind_int(idx, value)
+    }
+
+    fn bind_str(&self, idx: int, value: &str) {
+        self.cursor().bind_str(idx, value)
+    }
+
+    fn bind_f64(&self, idx: int, value: f64) {
+        self.cursor().bind_f64(idx, value)
+    }
+
+    fn is_null(&self, idx: int) -> bool {
+        self.cursor().is_null(idx)
+    }
+
+    fn get_prim_int(&self, idx: int) -> int {
+        self.cursor().get_prim_int(idx)
+    }
+
+    fn get_prim_str(&self, idx: int) -> String {
+        self.cursor().get_prim_str(idx)
+    }
+
+    fn get_prim_f64(&self, idx: int) -> f64 {
+        self.cursor().get_prim_f64(idx)
+    }
+
+    fn fetch_row(&self) -> bool {
+        self.cursor().fetch_row()
+    }
+}
+
+impl SqlAdapter for CachedDatabase {
+    fn dialect(&self) -> &'static SqlDialect {
+        self.db.dialect()
+    }
+
+    fn schema_binding(&self, key: TypeId) -> Option<Option<String>> {
+        self.schemas.borrow().find(&key).map(|schema| schema.clone())
+    }
+
+    fn try_execute(&self, query: &str) -> Result<(), String> {
+        self.db.try_execute(query)
+    }
+
+    fn execute_params(&self, query: &str, params: &[SqlValue]) {
+        let cursor = self.checkout(query);
+        adapter::bind_params(&cursor, params);
+        match cursor.cursor().step() {
+            sqlite3::SQLITE_DONE | sqlite3::SQLITE_ROW => (),
+            _ => fail!("{}", self.db.get_errmsg())
+        }
+    }
+
+    fn table_columns(&self, table: &str) -> Vec<ColumnInfo> {
+        self.db.table_columns(table)
+    }
+
+    fn table_exists<T:Table>(&self) -> bool {
+        adapter::sqlite_table_exists::<CachedDatabase, T>(self)
+    }
+
+    fn create_table_if_not_exists<T:Table>(&self) {
+        self.execute(dialect::create_table_query::<T>(self.dialect(), self.schema::<T>()).as_slice());
+        for query in dialect::create_index_queries::<T>(self.dialect(), self.schema::<T>()).iter() {
+            self.execute(query.as_slice());
+        }
+    }
+
+    fn insert_many<'r, T:Table, Iter: Iterator<&'r T>>(&self, records: Iter) -> Result<(), SqlError> {
+        let mut iter = records;
+        adapter::with_savepoint(self, false, || {
+            let cursor = match self.try_checkout(dialect::insert_query::<T>(self.dialect(), self.schema::<T>()).as_slice()) {
+                Ok(cursor) => cursor,
+                Err(msg) => return Err(SqlFailure(msg))
+            };
+            for record in iter.by_ref() {
+                record.bind(&cursor);
+                let result = cursor.cursor().step();
+                cursor.cursor().reset();
+                match result {
+                    sqlite3::SQLITE_DONE => (),
+                    sqlite3::SQLITE_CONSTRAINT =>
+                        return Err(adapter::constraint_error(super::table_name::<T>(), self.db.get_errmsg().as_slice())),
+                    _ => return Err(SqlFailure(self.db.get_errmsg()))
+                }
+            }
+            Ok(())
+        })
+    }
+
+    unsafe fn select_table<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlTableIter<'r, T> {
+        let cursor = self.checkout(query);
+        adapter::bind_params(&cursor, params);
+        SqlTableIter::new(self, box cursor)
+    }
+
+    unsafe fn select_columns<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlSelectIter<'r, T> {
+        let cursor = self.checkout(query);
+        adapter::bind_params(&cursor, params);
+        SqlSelectIter::new(self, box cursor)
+    }
+}
+
+// The type names `SqlPrimitive` declares are SQLite's, and the identifiers
+// `#[sql_table]` generates need no quoting.
+pub struct SqliteDialect;
+
+impl SqlDialect for SqliteDialect {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn typename(&self, typename: &str) -> String {
+        typename.to_string()
+    }
+
+    fn quote_identifier(&self, name: &str) -> String {
+        name.to_string()
+    }
+
+    fn placeholder(&self, _: uint) -> String {
+        "?".to_string()
+    }
+
+    // SQLite cannot add a column defaulting to the current time or to an
+    // expression in parentheses.
+    fn added_column_default(&self, default: &str) -> bool {
+        let default = default.trim().to_ascii_upper();
+        !default.as_slice().starts_with("(") && !default.as_slice().starts_with("CURRENT_")
+    }
+
+    // An index is created in the schema its name is qualified with, and
+    // always on a table of that schema.
+    fn index_on(&self, schema: Option<&str>, index: &str, table: &str) -> String {
+        match schema {
+            Some(schema) => format!("{}.{} ON {}", self.quote_identifier(schema),
+                                    self.quote_identifier(index), self.quote_identifier(table)),
+            None => format!("{} ON {}", self.quote_identifier(index), self.quote_identifier(table))
+        }
+    }
+}
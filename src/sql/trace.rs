@@ -0,0 +1,379 @@
+// An adapter which reports every query it runs to a `QueryLogger`, with the
+// parameters bound, the number of rows, the time taken and how it ended.
+
+use std::ascii::StrAsciiExt;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::intrinsics::TypeId;
+use std::task;
+use time;
+
+use super::{Table, SqlValue};
+use adapter::{SqlAdapter, SqlAdapterCursor, SqlTableIter, SqlSelectIter, ColumnInfo, SqlError, ValueCursor};
+use dialect;
+use dialect::SqlDialect;
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum QueryOutcome {
+    Succeeded,
+    // The error the query returned.
+    Failed(String),
+    // The query failed the task, as `execute_params` and the selects do on
+    // errors.
+    Aborted
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub struct QueryEvent {
+    pub query: String,
+    // The parameters of an `insert_many` are those of every row in turn.
+    // Redacted values are `None`.
+    pub params: Vec<Option<SqlValue>>,
+    // Rows fetched by a select or inserted by `insert_many`. `None` for other
+    // queries and for failed ones.
+    pub rows: Option<uint>,
+    // For selects, until the iterator is dropped.
+    pub elapsed_ns: u64,
+    pub outcome: QueryOutcome
+}
+
+pub trait QueryLogger {
+    fn log(&self, event: &QueryEvent);
+}
+
+impl QueryLogger for fn(&QueryEvent) {
+    fn log(&self, event: &QueryEvent) {
+        (*self)(event)
+    }
+}
+
+// An event logged when dropped, which also happens when the query fails the
+// task. It is `Aborted` until told otherwise.
+struct PendingEvent<'r> {
+    logger: &'r QueryLogger,
+    query: String,
+    params: Vec<Option<SqlValue>>,
+    rows: Option<uint>,
+    outcome: QueryOutcome,
+    start: u64
+}
+
+impl<'r> PendingEvent<'r> {
+    fn finish<T>(&mut self, result: &Result<T, String>) {
+        self.outcome = match *result {
+            Ok(_) => Succeeded,
+            Err(ref msg) => Failed(msg.clone())
+        };
+    }
+}
+
+#[unsafe_destructor]
+impl<'r> Drop for PendingEvent<'r> {
+    fn drop(&mut self) {
+        self.logger.log(&QueryEvent {
+            query: self.query.clone(),
+            params: self.params.clone(),
+            rows: self.rows,
+            elapsed_ns: time::precise_time_ns() - self.start,
+            outcome: self.outcome.clone()
+        })
+    }
+}
+
+enum Token {
+    Word(String),
+    Placeholder,
+    Symbol(char)
+}
+
+// Splits a query into identifiers, keywords, placeholders and symbols.
+// Quotes around identifiers are dropped and string literals skipped.
+fn tokens(query: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    loop {
+        let c = match chars.next() {
+            Some(c) => c,
+            None => return tokens
+        };
+        if c == '\'' {
+            for c in chars.by_ref() {
+                if c == '\'' {
+                    break
+                }
+            }
+        } else if c == '?' {
+            tokens.push(Placeholder);
+        } else if c == '$' && chars.peek().map_or(false, |c| c.is_digit()) {
+            while chars.peek().map_or(false, |c| c.is_digit()) {
+                chars.next();
+            }
+            tokens.push(Placeholder);
+        } else if c.is_alphanumeric() || c == '_' || c == '`' || c == '"' {
+            let mut word = String::new();
+            if c != '`' && c != '"' {
+                word.push_char(c);
+            }
+            loop {
+                match chars.peek() {
+                    Some(&c) if c.is_alphanumeric() || c == '_' || c == '.' => word.push_char(c),
+                    Some(&c) if c == '`' || c == '"' => (),
+                    _ => break
+                }
+                chars.next();
+            }
+            tokens.push(Word(word));
+        } else if !c.is_whitespace() {
+            tokens.push(Symbol(c));
+        }
+    }
+}
+
+// The word a placeholder is compared to, as in `a = ?` or `T.b LIKE ?`.
+fn compared_word<'r>(tokens: &'r [Token], placeholder: uint) -> Option<&'r str> {
+    let mut idx = placeholder;
+    while idx > 0 {
+        idx -= 1;
+        match tokens[idx] {
+            Symbol('=') | Symbol('<') | Symbol('>') | Symbol('!') => (),
+            Word(ref word) => {
+                let upper = word.as_slice().to_ascii_upper();
+                match upper.as_slice() {
+                    "LIKE" | "GLOB" | "NOT" | "IS" => (),
+                    _ => return if idx + 1 < placeholder { Some(word.as_slice()) } else { None }
+                }
+            },
+            _ => return None
+        }
+    }
+    None
+}
+
+fn logged_params(params: &[SqlValue]) -> Vec<Option<SqlValue>> {
+    params.iter().map(|param| Some(param.clone())).collect()
+}
+
+// Wraps `A`, which it derefs to. Table metadata lookups are not logged.
+pub struct TracedAdapter<A, L> {
+    inner: A,
+    logger: L,
+    redacted: HashSet<String>
+}
+
+impl<A: SqlAdapter, L: QueryLogger> TracedAdapter<A, L> {
+    pub fn new(inner: A, logger: L) -> TracedAdapter<A, L> {
+        TracedAdapter {
+            inner: inner,
+            logger: logger,
+            redacted: HashSet::new()
+        }
+    }
+
+    // Hides the values of `column` from the logger, given as `name` for every
+    // table or `Table.name`. The parameters of `insert_many` are those of the
+    // table's columns. In other queries which mention a redacted column, only
+    // parameters compared to a column which is not redacted are logged.
+    pub fn redact(&mut self, column: &str) {
+        self.redacted.insert(column.to_string());
+    }
+
+    pub fn logger<'r>(&'r self) -> &'r L {
+        &self.logger
+    }
+
+    fn is_redacted(&self, table: &str, column: &str) -> bool {
+        self.redacted.contains(&column.to_string()) ||
+            self.redacted.contains(&format!("{}.{}", table, column))
+    }
+
+    // Whether `word` of a query names a redacted column, unqualified names
+    // being taken as columns of every table the query mentions.
+    fn is_redacted_word(&self, word: &str, words: &[&str]) -> bool {
+        match word.rfind('.') {
+            Some(dot) => {
+                let table = word.slice_to(dot);
+                let table = table.slice_from(table.rfind('.').map_or(0, |dot| dot + 1));
+                self.is_redacted(table, word.slice_from(dot + 1))
+            },
+            None => words.iter().any(|table| self.is_redacted(*table, word))
+        }
+    }
+
+    fn logged_query_params(&self, query: &str, params: &[SqlValue]) -> Vec<Option<SqlValue>> {
+        if self.redacted.is_empty() {
+            return logged_params(params)
+        }
+        let tokens = tokens(query);
+        let mut words: Vec<&str> = Vec::new();
+        for token in tokens.iter() {
+            match *token {
+                Word(ref word) => words.push(word.as_slice()),
+                _ => ()
+            }
+        }
+        if !words.iter().any(|word| self.is_redacted_word(*word, words.as_slice())) {
+            return logged_params(params)
+        }
+        let mut shown = Vec::new();
+        for (idx, token) in tokens.iter().enumerate() {
+            match *token {
+                Placeholder => shown.push(match compared_word(tokens.as_slice(), idx) {
+                    Some(word) => !self.is_redacted_word(word, words.as_slice()),
+                    None => false
+                }),
+                _ => ()
+            }
+        }
+        params.iter().enumerate().map(|(idx, param)| {
+            if idx < shown.len() && *shown.get(idx) { Some(param.clone()) } else { None }
+        }).collect()
+    }
+
+    fn pending<'r>(&'r self, query: &str, params: Vec<Option<SqlValue>>) -> PendingEvent<'r> {
+        PendingEvent {
+            logger: &self.logger as &QueryLogger,
+            query: query.to_string(),
+            params: params,
+            rows: None,
+            outcome: Aborted,
+            start: time::precise_time_ns()
+        }
+    }
+}
+
+impl<A, L> Deref<A> for TracedAdapter<A, L> {
+    fn deref<'r>(&'r self) -> &'r A {
+        &self.inner
+    }
+}
+
+// Counts the rows fetched through it, and logs the select once dropped.
+struct TracedCursor<'r> {
+    event: PendingEvent<'r>,
+    cursor: Box<SqlAdapterCursor>,
+    rows: Cell<uint>
+}
+
+#[unsafe_destructor]
+impl<'r> Drop for TracedCursor<'r> {
+    fn drop(&mut self) {
+        if !task::failing() {
+            self.event.rows = Some(self.rows.get());
+            self.event.outcome = Succeeded;
+        }
+    }
+}
+
+impl<'r> SqlAdapterCursor for TracedCursor<'r> {
+    fn bind_null(&self, idx: int) {
+        self.cursor.bind_null(idx)
+    }
+
+    fn bind_int(&self, idx: int, value: int) {
+        self.cursor.bind_int(idx, value)
+    }
+
+    fn bind_str(&self, idx: int, value: &str) {
+        self.cursor.bind_str(idx, value)
+    }
+
+    fn bind_f64(&self, idx: int, value: f64) {
+        self.cursor.bind_f64(idx, value)
+    }
+
+    fn is_null(&self, idx: int) -> bool {
+        self.cursor.is_null(idx)
+    }
+
+    fn get_prim_int(&self, idx: int) -> int {
+        self.cursor.get_prim_int(idx)
+    }
+
+    fn get_prim_str(&self, idx: int) -> String {
+        self.cursor.get_prim_str(idx)
+    }
+
+    fn get_prim_f64(&self, idx: int) -> f64 {
+        self.cursor.get_prim_f64(idx)
+    }
+
+    fn fetch_row(&self) -> bool {
+        let fetched = self.cursor.fetch_row();
+        if fetched {
+            self.rows.set(self.rows.get() + 1);
+        }
+        fetched
+    }
+}
+
+impl<A: SqlAdapter, L: QueryLogger> SqlAdapter for TracedAdapter<A, L> {
+    fn dialect(&self) -> &'static SqlDialect {
+        self.inner.dialect()
+    }
+
+    fn schema_binding(&self, key: TypeId) -> Option<Option<String>> {
+        self.inner.schema_binding(key)
+    }
+
+    fn try_execute(&self, query: &str) -> Result<(), String> {
+        let mut event = self.pending(query, Vec::new());
+        let result = self.inner.try_execute(query);
+        event.finish(&result);
+        result
+    }
+
+    fn execute_params(&self, query: &str, params: &[SqlValue]) {
+        let mut event = self.pending(query, self.logged_query_params(query, params));
+        self.inner.execute_params(query, params);
+        event.outcome = Succeeded;
+    }
+
+    fn table_columns(&self, table: &str) -> Vec<ColumnInfo> {
+        self.inner.table_columns(table)
+    }
+
+    fn table_exists<T:Table>(&self) -> bool {
+        self.inner.table_exists::<T>()
+    }
+
+    fn create_table_if_not_exists<T:Table>(&self) {
+        let query = dialect::create_table_query::<T>(self.dialect(), self.schema::<T>());
+        let mut event = self.pending(query.as_slice(), Vec::new());
+        self.inner.create_table_if_not_exists::<T>();
+        event.outcome = Succeeded;
+    }
+
+    fn insert_many<'r, T:Table, Iter: Iterator<&'r T>>(&self, records: Iter) -> Result<(), SqlError> {
+        let records: Vec<&'r T> = records.collect();
+        let table = super::table_name::<T>();
+        let columns = super::columns::<T>();
+        let mut params = Vec::new();
+        for record in records.iter() {
+            let cursor = ValueCursor::new(Vec::new());
+            record.bind(&cursor);
+            for (column, param) in columns.iter().zip(cursor.params().move_iter()) {
+                params.push(if self.is_redacted(table, column.name) { None } else { Some(param) });
+            }
+        }
+        let query = dialect::insert_query::<T>(self.dialect(), self.schema::<T>());
+        let mut event = self.pending(query.as_slice(), params);
+        let result = self.inner.insert_many(records.iter().map(|record| *record));
+        event.finish(&result.clone().map_err(|e| e.to_str()));
+        if result.is_ok() {
+            event.rows = Some(records.len());
+        }
+        result
+    }
+
+    unsafe fn select_table<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlTableIter<'r, T> {
+        let event = self.pending(query, self.logged_query_params(query, params));
+        let cursor = self.inner.select_table::<T>(query, params).into_cursor();
+        SqlTableIter::new(self, box TracedCursor { event: event, cursor: cursor, rows: Cell::new(0) })
+    }
+
+    unsafe fn select_columns<'r, T>(&'r self, query: &str, params: &[SqlValue]) -> SqlSelectIter<'r, T> {
+        let event = self.pending(query, self.logged_query_params(query, params));
+        let cursor = self.inner.select_columns::<T>(query, params).into_cursor();
+        SqlSelectIter::new(self, box TracedCursor { event: event, cursor: cursor, rows: Cell::new(0) })
+    }
+}
@@ -0,0 +1,50 @@
+#![crate_id = "sql-codegen#0.1-pre"]
+
+#![comment = "Generates #[sql_table] structs from an existing SQLite database"]
+#![license = "MIT"]
+
+extern crate sql;
+extern crate sqlite3;
+
+use std::io;
+use std::os;
+
+use sql::adapter::SqlAdapter;
+
+fn main() {
+    let args = os::args();
+    let mut stderr = io::stderr();
+
+    if args.len() != 2 {
+        let _ = writeln!(&mut stderr, "usage: {} <database.sqlite3>", args.get(0));
+        os::set_exit_status(1);
+        return
+    }
+
+    let db = match sqlite3::open(args.get(1).as_slice()) {
+        Ok(db) => db,
+        Err(e) => {
+            let _ = writeln!(&mut stderr, "cannot open {}: {}", args.get(1), e);
+            os::set_exit_status(1);
+            return
+        }
+    };
+
+    let mut printed = false;
+    for table in sql::codegen::table_names(&db).iter() {
+        let columns = db.table_columns(table.as_slice());
+        match sql::codegen::table_struct(table.as_slice(), columns.as_slice()) {
+            Ok(code) => {
+                if printed {
+                    println!("");
+                }
+                print!("{}", code);
+                printed = true;
+            },
+            Err(msg) => {
+                let _ = writeln!(&mut stderr, "{}", msg);
+                os::set_exit_status(1);
+            }
+        }
+    }
+}
@@ -9,6 +9,7 @@
 extern crate syntax;
 
 use syntax::ast;
+use syntax::attr::AttrMetaMethods;
 use syntax::codemap;
 use syntax::ext::build::AstBuilder;
 use syntax::ext::base::{
@@ -27,15 +28,63 @@ pub fn macro_registrar(register: |ast::Name, SyntaxExtension|) {
 }
 
 struct TableExprs {
+    columns_expr: @ast::Expr,
+    constraints_expr: @ast::Expr,
+    indexes_expr: @ast::Expr,
     schema_expr: @ast::Expr,
-    insert_query_expr: @ast::Expr,
-    select_query_expr: @ast::Expr,
+    reference_checks_block: @ast::Block,
+    primary_key: Option<(ast::Ident, ast::P<ast::Ty>)>,
     bind_struct_block: @ast::Block,
     get_row_expr: @ast::Expr
 }
 
-fn coldef_typename(cx: &mut ExtCtxt, ty: ast::P<ast::Ty>) -> @ast::Expr {
-    quote_expr!(cx, sql::sql_typename::<$ty>())
+fn str_expr(cx: &mut ExtCtxt, span: codemap::Span, s: &str) -> @ast::Expr {
+    cx.expr_str(span, token::intern_and_get_ident(s))
+}
+
+fn opt_str_expr(cx: &mut ExtCtxt, span: codemap::Span, s: Option<&str>) -> @ast::Expr {
+    match s {
+        Some(s) => {
+            let s = str_expr(cx, span, s);
+            cx.expr_some(span, s)
+        },
+        None => cx.expr_none(span)
+    }
+}
+
+// A `Vec` of the given items. Unlike a fixed-size array, it needs no type
+// annotation when empty.
+fn vec_expr(cx: &mut ExtCtxt, span: codemap::Span, exprs: Vec<@ast::Expr>) -> @ast::Expr {
+    if exprs.is_empty() {
+        return quote_expr!(cx, Vec::new());
+    }
+    let mut stmts = vec![quote_stmt!(cx, let mut items = Vec::new();)];
+    for expr in exprs.move_iter() {
+        stmts.push(quote_stmt!(cx, items.push($expr);));
+    }
+    let items = quote_expr!(cx, items);
+    let block = cx.block(span, stmts, Some(items));
+    cx.expr_block(block)
+}
+
+fn str_vec_expr(cx: &mut ExtCtxt, span: codemap::Span, strs: &[String]) -> @ast::Expr {
+    let exprs = strs.iter().map(|s| str_expr(cx, span, s.as_slice())).collect();
+    vec_expr(cx, span, exprs)
+}
+
+// The `sql::Column` of a field, with the attribute settings applied as
+// assignments.
+fn column_expr(cx: &mut ExtCtxt, span: codemap::Span, name: @ast::Expr, ty: ast::P<ast::Ty>,
+               settings: Vec<@ast::Stmt>) -> @ast::Expr {
+    let column = quote_expr!(cx, sql::column::<$ty>($name));
+    if settings.is_empty() {
+        return column;
+    }
+    let mut stmts = vec![quote_stmt!(cx, let mut column = $column;)];
+    stmts.push_all_move(settings);
+    let result = quote_expr!(cx, column);
+    let block = cx.block(span, stmts, Some(result));
+    cx.expr_block(block)
 }
 
 fn bind_field_stmt(cx: &mut ExtCtxt,
@@ -53,6 +102,106 @@ fn get_field_expr(cx: &mut ExtCtxt,
     quote_expr!(cx, sql::SqlType::get_col(cursor, $idx_lit) )
 }
 
+// `#[sql_index(name, cols..., where = "...")]`. On a field, the field itself
+// is the first column and every argument is optional.
+struct IndexDef {
+    name: Option<String>,
+    columns: Vec<String>,
+    predicate: Option<String>
+}
+
+fn parse_index_attr(cx: &mut ExtCtxt, attr: &ast::Attribute) -> IndexDef {
+    let mut def = IndexDef { name: None, columns: Vec::new(), predicate: None };
+    match attr.meta_item_list() {
+        None => (),
+        Some(items) => for meta in items.iter() {
+            match meta.node {
+                ast::MetaWord(ref word) => {
+                    if def.name.is_none() {
+                        def.name = Some(word.get().to_string())
+                    } else {
+                        def.columns.push(word.get().to_string())
+                    }
+                },
+                ast::MetaNameValue(ref key, ref value) if key.get() == "where" => {
+                    match value.node {
+                        ast::LitStr(ref pred, _) => def.predicate = Some(pred.get().to_string()),
+                        _ => cx.span_err(meta.span, "`where` in #[sql_index] must be a string")
+                    }
+                },
+                _ => cx.span_err(meta.span, "unexpected argument to #[sql_index]")
+            }
+        }
+    }
+    def
+}
+
+// The plain column names listed in an attribute, as in `#[sql_unique(a, b)]`.
+fn attr_columns(cx: &mut ExtCtxt, attr: &ast::Attribute) -> Vec<String> {
+    let mut columns = Vec::new();
+    match attr.meta_item_list() {
+        None => cx.span_err(attr.span, format!("#[{}] needs a list of columns", attr.name()).as_slice()),
+        Some(items) => for meta in items.iter() {
+            match meta.node {
+                ast::MetaWord(ref word) => columns.push(word.get().to_string()),
+                _ => cx.span_err(meta.span, format!("expected column name in #[{}]", attr.name()).as_slice())
+            }
+        }
+    }
+    columns
+}
+
+fn check_attr_columns(cx: &mut ExtCtxt, attr: &ast::Attribute, columns: &[String], colnames: &[String]) {
+    for col in columns.iter() {
+        if !colnames.contains(col) {
+            cx.span_err(attr.span, format!("#[{}] names unknown column `{}`", attr.name(), col).as_slice());
+        }
+    }
+}
+
+// `#[sql_references(Table, on_delete = "...")]`
+fn parse_references_attr(cx: &mut ExtCtxt, attr: &ast::Attribute) -> Option<(ast::Ident, Option<String>)> {
+    let mut table = None;
+    let mut on_delete = None;
+    match attr.meta_item_list() {
+        None => (),
+        Some(items) => for meta in items.iter() {
+            match meta.node {
+                ast::MetaWord(ref word) if table.is_none() => table = Some(token::str_to_ident(word.get())),
+                ast::MetaNameValue(ref key, ref value) if key.get() == "on_delete" => {
+                    match value.node {
+                        ast::LitStr(ref action, _) => on_delete = Some(action.get().to_upper()),
+                        _ => cx.span_err(meta.span, "`on_delete` in #[sql_references] must be a string")
+                    }
+                },
+                _ => cx.span_err(meta.span, "unexpected argument to #[sql_references]")
+            }
+        }
+    }
+    match table {
+        None => {
+            cx.span_err(attr.span, "#[sql_references] needs the referenced table");
+            None
+        },
+        Some(table) => Some((table, on_delete))
+    }
+}
+
+fn index_expr(cx: &mut ExtCtxt, span: codemap::Span, table: &str, def: &IndexDef) -> @ast::Expr {
+    let name = match def.name {
+        Some(ref name) => name.clone(),
+        None => format!("{}_{}_idx", table, def.columns.connect("_"))
+    };
+    let name = str_expr(cx, span, name.as_slice());
+    let columns = str_vec_expr(cx, span, def.columns.as_slice());
+    let predicate = opt_str_expr(cx, span, def.predicate.as_ref().map(|pred| pred.as_slice()));
+    quote_expr!(cx, sql::Index {
+        name: $name,
+        columns: $columns,
+        predicate: $predicate
+    })
+}
+
 fn build_exprs(cx: &mut ExtCtxt,
                     span: codemap::Span,
                     item: @ast::Item) -> TableExprs {
@@ -69,11 +218,14 @@ fn build_exprs(cx: &mut ExtCtxt,
         _ => cx.span_bug(span, "#[sql_table] decorator only supports struct types")
     };
 
-    let mut coldefs = Vec::new();
+    let mut columns = Vec::new();
     let mut colnames = Vec::new();
-    let mut qmarks = Vec::new();
     let mut stmts = Vec::new();
     let mut fields = Vec::new();
+    let mut indexes = Vec::new();
+    let mut reference_checks = Vec::new();
+    let mut primary_key = None;
+    let table = item.ident.to_source();
 
     for (idx, field) in structdef.fields.iter().enumerate() {
         match field.node.kind {
@@ -81,14 +233,64 @@ fn build_exprs(cx: &mut ExtCtxt,
                 cx.span_bug(field.span, "#[sql_table] does not support unnamed struct"),
             ast::NamedField(ref ident, _) => {
                 let ty = field.node.ty;
-                let tuple = ast::ExprTup(vec![
-                    cx.expr_str(span, token::intern_and_get_ident(ident.to_source().as_slice())),
-                    coldef_typename(cx, ty) 
-                ]);
+                let name = str_expr(cx, span, ident.to_source().as_slice());
+                let mut settings = Vec::new();
+                let mut has_default = false;
+                for attr in field.node.attrs.iter() {
+                    if attr.check_name("sql_default") {
+                        if has_default {
+                            cx.span_err(attr.span, "a field supports a single #[sql_default]");
+                        }
+                        has_default = true;
+                        match attr.value_str() {
+                            Some(value) => {
+                                let value = cx.expr_str(span, value);
+                                settings.push(quote_stmt!(cx, column.default = Some($value);));
+                            },
+                            None => cx.span_err(attr.span, "#[sql_default] needs a value, as in #[sql_default = \"0\"]")
+                        }
+                    } else if attr.check_name("sql_check") {
+                        match attr.value_str() {
+                            Some(expr) => {
+                                let expr = cx.expr_str(span, expr);
+                                settings.push(quote_stmt!(cx, column.checks.push($expr);));
+                            },
+                            None => cx.span_err(attr.span, "#[sql_check] needs an expression, as in #[sql_check = \"a > 0\"]")
+                        }
+                    } else if attr.check_name("sql_primary_key") {
+                        if primary_key.is_some() {
+                            cx.span_err(attr.span, "#[sql_table] supports a single #[sql_primary_key] field");
+                        }
+                        primary_key = Some((ident.clone(), ty));
+                        settings.push(quote_stmt!(cx, column.primary_key = true;));
+                    } else if attr.check_name("sql_unique") {
+                        settings.push(quote_stmt!(cx, column.unique = true;));
+                    } else if attr.check_name("sql_references") {
+                        match parse_references_attr(cx, attr) {
+                            None => (),
+                            Some((other, on_delete)) => {
+                                let on_delete = opt_str_expr(cx, span, on_delete.as_ref().map(|action| action.as_slice()));
+                                reference_checks.push(quote_stmt!(cx,
+                                    sql::check_reference(None::<&$ty>, None::<&$other>);
+                                ));
+                                settings.push(quote_stmt!(cx,
+                                    column.references = Some(sql::Reference {
+                                        table: sql::table_name::<$other>().to_string(),
+                                        column: sql::PrimaryKey::primary_key_name(None::<&$other>).to_string(),
+                                        on_delete: $on_delete
+                                    });
+                                ));
+                            }
+                        }
+                    } else if attr.check_name("sql_index") {
+                        let mut def = parse_index_attr(cx, attr);
+                        def.columns.unshift(ident.to_source());
+                        indexes.push(index_expr(cx, span, table.as_slice(), &def));
+                    }
+                }
 
-                coldefs.push(cx.expr(span, tuple));
+                columns.push(column_expr(cx, span, name, ty, settings));
                 colnames.push(ident.to_source());
-                qmarks.push("?");
                 stmts.push(bind_field_stmt(cx, span, ident, (idx+1) as int));
                 fields.push(ast::Field {
                     ident: codemap::Spanned { node: ident.clone(), span: span },
@@ -99,19 +301,49 @@ fn build_exprs(cx: &mut ExtCtxt,
         }
     }
 
-    let vec_expr = cx.expr_vec(span, coldefs);
+    let mut constraints = Vec::new();
+    let mut table_checks = 0u;
+    let mut schema = None;
 
-    let insert_query = format!("INSERT INTO {} ({}) VALUES ({});",
-                            item.ident.to_source(), 
-                            colnames.connect(", "),
-                            qmarks.connect(", "));
-
-    let select_query = format!("SELECT * FROM {};", item.ident.to_source());
+    for attr in item.attrs.iter() {
+        if attr.check_name("sql_index") {
+            let def = parse_index_attr(cx, attr);
+            if def.name.is_none() || def.columns.is_empty() {
+                cx.span_err(attr.span, "#[sql_index] on a struct needs a name and columns");
+                continue;
+            }
+            check_attr_columns(cx, attr, def.columns.as_slice(), colnames.as_slice());
+            indexes.push(index_expr(cx, span, table.as_slice(), &def));
+        } else if attr.check_name("sql_unique") {
+            let unique_columns = attr_columns(cx, attr);
+            check_attr_columns(cx, attr, unique_columns.as_slice(), colnames.as_slice());
+            let unique_columns = str_vec_expr(cx, span, unique_columns.as_slice());
+            constraints.push(quote_expr!(cx, sql::UniqueConstraint($unique_columns)));
+        } else if attr.check_name("sql_check") {
+            match attr.value_str() {
+                Some(expr) => {
+                    table_checks += 1;
+                    let name = str_expr(cx, span, format!("{}_check_{}", table, table_checks).as_slice());
+                    let expr = cx.expr_str(span, expr);
+                    constraints.push(quote_expr!(cx, sql::CheckConstraint($name, $expr)));
+                },
+                None => cx.span_err(attr.span, "#[sql_check] needs an expression, as in #[sql_check = \"a > 0\"]")
+            }
+        } else if attr.check_name("sql_schema") {
+            match attr.value_str() {
+                Some(name) => schema = Some(name.get().to_string()),
+                None => cx.span_err(attr.span, "#[sql_schema] needs a name, as in #[sql_schema = \"archive\"]")
+            }
+        }
+    }
 
     TableExprs {
-        schema_expr: vec_expr,
-        insert_query_expr: cx.expr_str(span, token::intern_and_get_ident(insert_query.as_slice())),
-        select_query_expr: cx.expr_str(span, token::intern_and_get_ident(select_query.as_slice())),
+        columns_expr: vec_expr(cx, span, columns),
+        constraints_expr: vec_expr(cx, span, constraints),
+        indexes_expr: vec_expr(cx, span, indexes),
+        schema_expr: opt_str_expr(cx, span, schema.as_ref().map(|name| name.as_slice())),
+        reference_checks_block: cx.block(span, reference_checks, None),
+        primary_key: primary_key,
         bind_struct_block: cx.block(span, stmts, None),
         get_row_expr: cx.expr_struct_ident(span, item.ident, fields)
     }
@@ -127,9 +359,11 @@ fn expand_table(cx: &mut ExtCtxt,
     let table_name = item.ident;
     let tablename_tok = token::intern_and_get_ident(item.ident.to_source().as_slice());
     let table_name_str = cx.expr_str(span, tablename_tok);
+    let columns = table_exprs.columns_expr;
+    let constraints = table_exprs.constraints_expr;
+    let indexes = table_exprs.indexes_expr;
     let schema = table_exprs.schema_expr;
-    let insert_query = table_exprs.insert_query_expr;
-    let select_query = table_exprs.select_query_expr;
+    let reference_checks = table_exprs.reference_checks_block;
     let bind_block = table_exprs.bind_struct_block;
     let get_row = table_exprs.get_row_expr;
 
@@ -139,21 +373,22 @@ fn expand_table(cx: &mut ExtCtxt,
                 $table_name_str
             }
 
-            fn create_table_query(_: Option<&$table_name>) -> String {
-                let coldefs:Vec<String> = $schema.iter().map(|&(colname, ref typename)| {
-                    format!("{} {}", colname, typename.as_slice())
-                }).collect();
+            fn schema(_: Option<&$table_name>) -> Option<&'static str> {
+                $schema
+            }
+
+            fn columns(_: Option<&$table_name>) -> Vec<sql::Column> {
+                $reference_checks
 
-                let table_name = sql::table_name::<$table_name>();
-                format!("CREATE TABLE IF NOT EXISTS {} ({});", table_name, coldefs.connect(", "))
+                $columns
             }
 
-            fn insert_query(_: Option<&$table_name>) -> &str {
-                $insert_query
+            fn constraints(_: Option<&$table_name>) -> Vec<sql::Constraint> {
+                $constraints
             }
 
-            fn select_query(_: Option<&$table_name>) -> &str {
-                $select_query
+            fn indexes(_: Option<&$table_name>) -> Vec<sql::Index> {
+                $indexes
             }
 
             fn bind(&self, cursor: &sql::adapter::SqlAdapterCursor) {
@@ -167,57 +402,536 @@ fn expand_table(cx: &mut ExtCtxt,
     );
 
     push(trait_item.unwrap());
+
+    match table_exprs.primary_key {
+        None => (),
+        Some((key, key_ty)) => {
+            let key_str = cx.expr_str(span, token::intern_and_get_ident(key.to_source().as_slice()));
+            let key_item = quote_item!(cx,
+                impl sql::PrimaryKey<$key_ty> for $table_name {
+                    fn primary_key_name(_: Option<&$table_name>) -> &str {
+                        $key_str
+                    }
+                }
+            );
+            push(key_item.unwrap());
+        }
+    }
 }
 
 fn expand_sql_ext(cx: &mut ExtCtxt, sp: codemap::Span, tts: &[ast::TokenTree])
                 -> Box<MacResult> {
     match parse_sql(cx, tts) {
         None => MacExpr::new(cx.expr_str(sp, token::intern_and_get_ident(""))),
-        Some(SelectQuery { selector: AllColumns, tablename: table }) => {
-            let query = format!("SELECT * FROM {};", table.to_source());
-            let query_str = cx.expr_str(sp, token::intern_and_get_ident(query.as_slice()));
-            let selector = quote_expr!(cx, sql::selector::table_selector::<$table>($query_str));
-            MacExpr::new(selector)
-        },
-        Some(SelectQuery { selector: Columns(cols), tablename: table }) => {
-            let col_list:Vec<String> = cols.iter().map(|id| id.to_source()).collect();
-            let query = format!("SELECT {} FROM {};", col_list.connect(", "), table.to_source());
-            let query_str = cx.expr_str(sp, token::intern_and_get_ident(query.as_slice()));
-            let tuple_expr = column_tuple(cx, sp, cols);
-            let selector = quote_expr!(cx, {
-                let dummy_tab = None::<$table>;
-                let dummy_cols = dummy_tab.map(|tab| $tuple_expr);
-                sql::selector::column_selector($query_str, dummy_cols)
-            });
+        Some(SelectQuery(select)) => {
+            let table = select.tablename;
+            let checks = select_checks(cx, sp, &select, 0, None);
+            let scope = Scope::new(&select, 0);
+
+            let mut gen = QueryGen::new();
+            emit_select(cx, sp, &mut gen, &select);
+            gen.push_str(";");
+            let query = gen.finish(cx, sp);
+
+            let selector = match (&select.selector, select.into) {
+                (&AllColumns, _) if !select.joins.is_empty() => {
+                    cx.span_err(sp, "`select *` reads the rows of a single table, list the columns of a `join`");
+                    return MacExpr::new(cx.expr_str(sp, token::intern_and_get_ident("")))
+                },
+                (&AllColumns, None) => quote_expr!(cx, {
+                    let _ = $checks;
+                    sql::selector::table_selector::<$table>($query)
+                }),
+                (&AllColumns, Some(_)) => {
+                    cx.span_err(sp, "`into` requires an explicit select list");
+                    return MacExpr::new(cx.expr_str(sp, token::intern_and_get_ident("")))
+                },
+                (&Columns(ref items), None) => {
+                    let tuple_expr = column_tuple(cx, sp, &scope, items.as_slice());
+                    let dummy_cols = scope.wrap(cx, sp, tuple_expr);
+                    quote_expr!(cx, {
+                        let _ = $checks;
+                        let dummy_cols = $dummy_cols;
+                        sql::selector::column_selector($query, dummy_cols)
+                    })
+                },
+                (&Columns(ref items), Some(row_ty)) => {
+                    let (struct_expr, get_row) = match column_struct(cx, sp, &scope, row_ty, items.as_slice()) {
+                        Some(exprs) => exprs,
+                        None => return MacExpr::new(cx.expr_str(sp, token::intern_and_get_ident("")))
+                    };
+                    let dummy_row = scope.wrap(cx, sp, struct_expr);
+                    quote_expr!(cx, {
+                        let _ = $checks;
+                        let _ = $dummy_row;
+                        fn sql_get_row(cursor: &sql::adapter::SqlAdapterCursor) -> $row_ty {
+                            $get_row
+                        }
+                        sql::selector::row_selector($query, sql_get_row)
+                    })
+                }
+            };
             MacExpr::new(selector)
         },
     }
 }
 
-fn column_tuple(cx: &mut ExtCtxt, sp: codemap::Span, cols: Vec<ast::Ident>) -> @ast::Expr {
-    let tab = cx.expr_ident(sp, token::str_to_ident("tab"));
-    let exprs = cols.move_iter().map(|colname|
-        cx.expr_field_access(sp, tab, colname)
+fn column_tuple(cx: &mut ExtCtxt, sp: codemap::Span, scope: &Scope, items: &[SelectItem]) -> @ast::Expr {
+    let exprs = items.iter().map(|item|
+        column_value(cx, sp, scope, &item.expr)
     ).collect();
 
     cx.expr(sp, ast::ExprTup(exprs))
 }
 
+// Builds the struct literal used to type check a select list against the
+// fields of `row_ty`, and the one reading it back from a cursor.
+// Fields are named after the alias of each item, or the column it selects.
+fn column_struct(cx: &mut ExtCtxt, sp: codemap::Span, scope: &Scope, row_ty: ast::Ident,
+                 items: &[SelectItem]) -> Option<(@ast::Expr, @ast::Expr)> {
+    let mut check_fields = Vec::new();
+    let mut get_fields = Vec::new();
+
+    for (idx, item) in items.iter().enumerate() {
+        let name = match (item.alias, &item.expr) {
+            (Some(alias), _) => alias,
+            (None, &ColumnRef(col)) => col.name,
+            (None, _) => {
+                cx.span_err(sp, "expressions selected `into` a struct need an alias");
+                return None
+            }
+        };
+        check_fields.push(cx.field_imm(sp, name, column_value(cx, sp, scope, &item.expr)));
+        get_fields.push(cx.field_imm(sp, name, get_field_expr(cx, sp, idx as int)));
+    }
+
+    Some((cx.expr_struct_ident(sp, row_ty, check_fields),
+          cx.expr_struct_ident(sp, row_ty, get_fields)))
+}
+
+// An expression with the Rust type of a select list item, for type inference.
+fn column_value(cx: &mut ExtCtxt, sp: codemap::Span, scope: &Scope, expr: &ColumnExpr) -> @ast::Expr {
+    match *expr {
+        ColumnRef(col) => {
+            let field = scope.field(cx, sp, col);
+            quote_expr!(cx, sql::selector::column_value($field))
+        },
+        ValueExpr(value) => quote_expr!(cx, sql::selector::column_value(&$value)),
+        ArithExpr(ref lhs, op, ref rhs) => {
+            let lhs_value = column_value(cx, sp, scope, *lhs);
+            let rhs_value = column_value(cx, sp, scope, *rhs);
+            if op == "/" || op == "%" {
+                quote_expr!(cx, sql::selector::divide_value(&$lhs_value, &$rhs_value))
+            } else {
+                quote_expr!(cx, sql::selector::arith_value(&$lhs_value, &$rhs_value))
+            }
+        },
+        ConcatExpr(ref lhs, ref rhs) => {
+            let lhs_value = column_value(cx, sp, scope, *lhs);
+            let rhs_value = column_value(cx, sp, scope, *rhs);
+            quote_expr!(cx, sql::selector::concat_value(&$lhs_value, &$rhs_value))
+        }
+    }
+}
+
+// Builds the statements assembling a `sql::selector::QueryBuf` at runtime.
+// Literal SQL text is accumulated and flushed whenever a parameter is bound.
+struct QueryGen {
+    text: String,
+    stmts: Vec<@ast::Stmt>
+}
+
+impl QueryGen {
+    fn new() -> QueryGen {
+        QueryGen { text: String::new(), stmts: Vec::new() }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.text.push_str(s)
+    }
+
+    fn flush(&mut self, cx: &mut ExtCtxt, sp: codemap::Span) {
+        if !self.text.is_empty() {
+            let text = cx.expr_str(sp, token::intern_and_get_ident(self.text.as_slice()));
+            self.stmts.push(quote_stmt!(cx, sql_query_buf.push_str($text); ));
+            self.text = String::new();
+        }
+    }
+
+    fn push_param(&mut self, cx: &mut ExtCtxt, sp: codemap::Span, expr: @ast::Expr) {
+        self.flush(cx, sp);
+        self.stmts.push(quote_stmt!(cx, sql_query_buf.push_param(&$expr); ));
+    }
+
+    // The table name is qualified at runtime, with the schema the connection
+    // the query is fetched from binds it to.
+    fn push_table(&mut self, cx: &mut ExtCtxt, sp: codemap::Span, table: ast::Ident) {
+        self.flush(cx, sp);
+        self.stmts.push(quote_stmt!(cx, sql_query_buf.push_table::<$table>(); ));
+    }
+
+    fn push_comparison(&mut self, cx: &mut ExtCtxt, sp: codemap::Span, column: &str, op: &str,
+                       expr: @ast::Expr) {
+        self.flush(cx, sp);
+        let column = str_expr(cx, sp, column);
+        let op = str_expr(cx, sp, op);
+        self.stmts.push(quote_stmt!(cx, sql_query_buf.push_comparison($column, $op, &$expr); ));
+    }
+
+    fn push_in_list(&mut self, cx: &mut ExtCtxt, sp: codemap::Span, column: &str, expr: @ast::Expr) {
+        self.flush(cx, sp);
+        let column = str_expr(cx, sp, column);
+        self.stmts.push(quote_stmt!(cx, sql_query_buf.push_in_list($column, $expr.as_slice()); ));
+    }
+
+    fn finish(mut self, cx: &mut ExtCtxt, sp: codemap::Span) -> @ast::Expr {
+        self.flush(cx, sp);
+        let mut stmts = vec![quote_stmt!(cx, let mut sql_query_buf = sql::selector::QueryBuf::new(); )];
+        stmts.push_all_move(self.stmts);
+        let result = cx.expr_ident(sp, token::str_to_ident("sql_query_buf"));
+        cx.expr_block(cx.block(sp, stmts, Some(result)))
+    }
+}
+
+fn emit_select(cx: &mut ExtCtxt, sp: codemap::Span, gen: &mut QueryGen, select: &Select) {
+    gen.push_str(if select.distinct { "SELECT DISTINCT " } else { "SELECT " });
+    match select.selector {
+        AllColumns => gen.push_str("*"),
+        Columns(ref items) => {
+            for (i, item) in items.iter().enumerate() {
+                if i != 0 {
+                    gen.push_str(", ");
+                }
+                emit_column_expr(cx, sp, gen, &item.expr, false);
+                match item.alias {
+                    Some(alias) => gen.push_str(format!(" AS {}", alias.to_source()).as_slice()),
+                    None => ()
+                }
+            }
+        }
+    }
+    gen.push_str(" FROM ");
+    gen.push_table(cx, sp, select.tablename);
+    for join in select.joins.iter() {
+        gen.push_str(" JOIN ");
+        gen.push_table(cx, sp, join.tablename);
+        gen.push_str(" ON ");
+        emit_predicate(cx, sp, gen, &join.on);
+    }
+
+    match select.predicate {
+        None => (),
+        Some(ref pred) => {
+            gen.push_str(" WHERE ");
+            emit_predicate(cx, sp, gen, pred);
+        }
+    }
+}
+
+fn emit_column_expr(cx: &mut ExtCtxt, sp: codemap::Span, gen: &mut QueryGen,
+                    expr: &ColumnExpr, nested: bool) {
+    match *expr {
+        ColumnRef(col) => gen.push_str(column_sql(col).as_slice()),
+        ValueExpr(value) => gen.push_param(cx, sp, value),
+        ArithExpr(ref lhs, op, ref rhs) => {
+            if nested { gen.push_str("("); }
+            emit_column_expr(cx, sp, gen, *lhs, true);
+            gen.push_str(format!(" {} ", op).as_slice());
+            emit_column_expr(cx, sp, gen, *rhs, true);
+            if nested { gen.push_str(")"); }
+        },
+        ConcatExpr(ref lhs, ref rhs) => {
+            if nested { gen.push_str("("); }
+            emit_column_expr(cx, sp, gen, *lhs, true);
+            gen.push_str(" || ");
+            emit_column_expr(cx, sp, gen, *rhs, true);
+            if nested { gen.push_str(")"); }
+        }
+    }
+}
+
+fn emit_predicate(cx: &mut ExtCtxt, sp: codemap::Span, gen: &mut QueryGen, pred: &Predicate) {
+    match *pred {
+        AndPred(ref lhs, ref rhs) => {
+            gen.push_str("(");
+            emit_predicate(cx, sp, gen, *lhs);
+            gen.push_str(" AND ");
+            emit_predicate(cx, sp, gen, *rhs);
+            gen.push_str(")");
+        },
+        OrPred(ref lhs, ref rhs) => {
+            gen.push_str("(");
+            emit_predicate(cx, sp, gen, *lhs);
+            gen.push_str(" OR ");
+            emit_predicate(cx, sp, gen, *rhs);
+            gen.push_str(")");
+        },
+        NotPred(ref inner) => {
+            gen.push_str("NOT (");
+            emit_predicate(cx, sp, gen, *inner);
+            gen.push_str(")");
+        },
+        ComparePred(col, op, ParamOperand(expr)) if op == "=" || op == "!=" =>
+            gen.push_comparison(cx, sp, column_sql(col).as_slice(), op, expr),
+        ComparePred(col, op, ref operand) => {
+            gen.push_str(format!("{} {} ", column_sql(col), op).as_slice());
+            match *operand {
+                ParamOperand(expr) => gen.push_param(cx, sp, expr),
+                ColumnOperand(other) => gen.push_str(column_sql(other).as_slice())
+            }
+        },
+        IsNullPred(col, negated) => {
+            let test = if negated { "IS NOT NULL" } else { "IS NULL" };
+            gen.push_str(format!("{} {}", column_sql(col), test).as_slice());
+        },
+        MatchPred(col, op, ref operand) => {
+            gen.push_str(format!("{} {} ", column_sql(col), op).as_slice());
+            match *operand {
+                ParamOperand(expr) => gen.push_param(cx, sp, expr),
+                ColumnOperand(other) => gen.push_str(column_sql(other).as_slice())
+            }
+        },
+        InList(col, expr) => gen.push_in_list(cx, sp, column_sql(col).as_slice(), expr),
+        InSelect(col, ref sub) => {
+            gen.push_str(format!("{} IN (", column_sql(col)).as_slice());
+            emit_select(cx, sp, gen, *sub);
+            gen.push_str(")");
+        }
+    }
+}
+
+fn column_sql(col: ColRef) -> String {
+    match col.table {
+        Some(table) => format!("{}.{}", table.to_source(), col.name.to_source()),
+        None => col.name.to_source()
+    }
+}
+
+fn tab_ident(depth: uint) -> ast::Ident {
+    token::str_to_ident(format!("tab{}", depth).as_slice())
+}
+
+// The tables of a query, each bound to an argument of the closures the type
+// checks run in. Columns of a query with joins must name their table.
+struct Scope {
+    tables: Vec<(ast::Ident, ast::Ident)>,
+    joined: bool
+}
+
+impl Scope {
+    fn new(select: &Select, depth: uint) -> Scope {
+        let mut tables = vec![(select.tablename, tab_ident(depth))];
+        for (i, join) in select.joins.iter().enumerate() {
+            let binding = token::str_to_ident(format!("tab{}_{}", depth, i + 1).as_slice());
+            tables.push((join.tablename, binding));
+        }
+        Scope { tables: tables, joined: !select.joins.is_empty() }
+    }
+
+    fn first_binding(&self) -> ast::Ident {
+        let &(_, binding) = self.tables.get(0);
+        binding
+    }
+
+    // `&tabN.col` for a column of one of the tables.
+    fn field(&self, cx: &mut ExtCtxt, sp: codemap::Span, col: ColRef) -> @ast::Expr {
+        let binding = match col.table {
+            Some(table) => match self.tables.iter().find(|&&(name, _)| name.name == table.name) {
+                Some(&(_, binding)) => binding,
+                None => {
+                    cx.span_err(sp, format!("table `{}` is not part of the query", table.to_source()).as_slice());
+                    self.first_binding()
+                }
+            },
+            None => {
+                if self.joined {
+                    cx.span_err(sp, format!("column `{}` must name its table in a query with `join`",
+                                            col.name.to_source()).as_slice());
+                }
+                self.first_binding()
+            }
+        };
+        let tab = cx.expr_ident(sp, binding);
+        cx.expr_addr_of(sp, cx.expr_field_access(sp, tab, col.name))
+    }
+
+    // Builds `None::<T0>.and_then(|tab0| None::<T1>.map(|tab0_1| body))`,
+    // which never runs but binds each table for `body`.
+    fn wrap(&self, cx: &mut ExtCtxt, sp: codemap::Span, body: @ast::Expr) -> @ast::Expr {
+        let last = self.tables.len() - 1;
+        let mut expr = body;
+        for i in range(0, self.tables.len()).rev() {
+            let &(table, binding) = self.tables.get(i);
+            expr = if i == last {
+                quote_expr!(cx, None::<$table>.map(|$binding| $expr))
+            } else {
+                quote_expr!(cx, None::<$table>.and_then(|$binding| $expr))
+            };
+        }
+        expr
+    }
+}
+
+// Builds the closures of `Scope::wrap` around checks of every column and
+// parameter in the ON and WHERE clauses.
+// `outer` is the column of the enclosing query compared against a subquery.
+fn select_checks(cx: &mut ExtCtxt, sp: codemap::Span, select: &Select, depth: uint,
+                 outer: Option<@ast::Expr>) -> @ast::Expr {
+    let scope = Scope::new(select, depth);
+    let mut stmts = Vec::new();
+
+    match (outer, &select.selector) {
+        (Some(outer_col), &Columns(ref items)) => {
+            let col = column_value(cx, sp, &scope, &items.get(0).expr);
+            stmts.push(quote_stmt!(cx, sql::selector::check_column($outer_col, &$col); ));
+        },
+        _ => ()
+    }
+
+    for join in select.joins.iter() {
+        predicate_checks(cx, sp, &scope, depth, &join.on, &mut stmts);
+    }
+
+    match select.predicate {
+        None => (),
+        Some(ref pred) => predicate_checks(cx, sp, &scope, depth, pred, &mut stmts)
+    }
+
+    let block = cx.block(sp, stmts, None);
+    let body = cx.expr_block(block);
+    scope.wrap(cx, sp, body)
+}
+
+fn predicate_checks(cx: &mut ExtCtxt, sp: codemap::Span, scope: &Scope, depth: uint,
+                    pred: &Predicate, stmts: &mut Vec<@ast::Stmt>) {
+    match *pred {
+        AndPred(ref lhs, ref rhs) | OrPred(ref lhs, ref rhs) => {
+            predicate_checks(cx, sp, scope, depth, *lhs, stmts);
+            predicate_checks(cx, sp, scope, depth, *rhs, stmts);
+        },
+        NotPred(ref inner) => predicate_checks(cx, sp, scope, depth, *inner, stmts),
+        ComparePred(col, op, ParamOperand(expr)) if op == "=" || op == "!=" => {
+            let field = scope.field(cx, sp, col);
+            stmts.push(quote_stmt!(cx, sql::selector::check_param($field, &$expr); ));
+        },
+        // NULL is not ordered, so the parameter can't be an `Option`.
+        ComparePred(col, _, ParamOperand(expr)) => {
+            let field = scope.field(cx, sp, col);
+            stmts.push(quote_stmt!(cx, sql::selector::check_ordered_param($field, &$expr); ));
+        },
+        ComparePred(col, _, ColumnOperand(other)) => {
+            let field = scope.field(cx, sp, col);
+            let other_field = scope.field(cx, sp, other);
+            stmts.push(quote_stmt!(cx, sql::selector::check_column($field, $other_field); ));
+        },
+        IsNullPred(col, _) => {
+            let field = scope.field(cx, sp, col);
+            stmts.push(quote_stmt!(cx, sql::selector::check_nullable($field); ));
+        },
+        MatchPred(col, _, ParamOperand(expr)) => {
+            let field = scope.field(cx, sp, col);
+            stmts.push(quote_stmt!(cx, sql::selector::check_pattern($field, &$expr); ));
+        },
+        MatchPred(col, _, ColumnOperand(other)) => {
+            let field = scope.field(cx, sp, col);
+            let other_field = scope.field(cx, sp, other);
+            stmts.push(quote_stmt!(cx, sql::selector::check_pattern($field, $other_field); ));
+        },
+        InList(col, expr) => {
+            let field = scope.field(cx, sp, col);
+            stmts.push(quote_stmt!(cx, sql::selector::check_in($field, $expr.as_slice()); ));
+        },
+        InSelect(col, ref sub) => {
+            let field = scope.field(cx, sp, col);
+            let sub_checks = select_checks(cx, sp, *sub, depth + 1, Some(field));
+            stmts.push(cx.stmt_expr(sub_checks));
+        }
+    }
+}
+
 enum SqlAst {
-    SelectQuery { selector: SelectColumns, tablename: ast::Ident }
+    SelectQuery(Select)
+}
+
+struct Select {
+    distinct: bool,
+    selector: SelectColumns,
+    tablename: ast::Ident,
+    joins: Vec<Join>,
+    predicate: Option<Predicate>,
+    into: Option<ast::Ident>
+}
+
+struct Join {
+    tablename: ast::Ident,
+    on: Predicate
+}
+
+// A column, optionally qualified with its table as in `Post.title`.
+struct ColRef {
+    table: Option<ast::Ident>,
+    name: ast::Ident
 }
 
 enum SelectColumns {
     AllColumns,
-    Columns(Vec<ast::Ident>)
+    Columns(Vec<SelectItem>)
+}
+
+struct SelectItem {
+    expr: ColumnExpr,
+    alias: Option<ast::Ident>
+}
+
+enum ColumnExpr {
+    ColumnRef(ColRef),
+    ValueExpr(@ast::Expr),
+    ArithExpr(Box<ColumnExpr>, &'static str, Box<ColumnExpr>),
+    ConcatExpr(Box<ColumnExpr>, Box<ColumnExpr>)
+}
+
+enum Predicate {
+    AndPred(Box<Predicate>, Box<Predicate>),
+    OrPred(Box<Predicate>, Box<Predicate>),
+    NotPred(Box<Predicate>),
+    ComparePred(ColRef, &'static str, Operand),
+    IsNullPred(ColRef, bool),
+    MatchPred(ColRef, &'static str, Operand),
+    InList(ColRef, @ast::Expr),
+    InSelect(ColRef, Box<Select>)
+}
+
+enum Operand {
+    ParamOperand(@ast::Expr),
+    ColumnOperand(ColRef)
+}
+
+fn is_word(p: &Parser, word: &str) -> bool {
+    match p.token {
+        token::IDENT(ident, _) => token::get_ident(ident).get() == word,
+        _ => false
+    }
+}
+
+fn eat_word(p: &mut Parser, word: &str) -> bool {
+    if is_word(p, word) {
+        p.bump();
+        true
+    } else {
+        false
+    }
 }
 
 fn parse_sql(cx: &ExtCtxt, tts: &[ast::TokenTree]) -> Option<SqlAst> {
     let p = &mut parse::new_parser_from_tts(cx.parse_sess(), cx.cfg(), Vec::from_slice(tts));
     match p.parse_ident().to_source().as_slice() {
         "select" =>
-            parse_select(cx, p).map(|(cols, tablename)| {
-                SelectQuery { selector: cols, tablename: tablename }
+            parse_select(cx, p).map(|mut select| {
+                if eat_word(p, "into") {
+                    select.into = Some(p.parse_ident());
+                }
+                p.expect(&token::EOF);
+                SelectQuery(select)
             }),
         o => {
             cx.span_err(p.last_span, format!("unknown SQL directive {}", o).as_slice());
@@ -226,13 +940,40 @@ fn parse_sql(cx: &ExtCtxt, tts: &[ast::TokenTree]) -> Option<SqlAst> {
     }
 }
 
-fn parse_select<'r>(cx: &ExtCtxt, p: &mut Parser<'r>) -> Option<(SelectColumns, ast::Ident)> {
+fn parse_select<'r>(cx: &ExtCtxt, p: &mut Parser<'r>) -> Option<Select> {
+    let distinct = eat_word(p, "distinct");
     parse_columns(cx, p).and_then(|cols| {
         match p.parse_ident().to_source().as_slice() {
             "from" => {
                 let tablename = p.parse_ident();
-                p.expect(&token::EOF);
-                Some((cols, tablename))
+                let mut joins = Vec::new();
+                while eat_word(p, "join") {
+                    let joined = p.parse_ident();
+                    if !eat_word(p, "on") {
+                        cx.span_err(p.span, "expected `on` after the joined table");
+                        return None
+                    }
+                    match parse_predicate(cx, p) {
+                        Some(on) => joins.push(Join { tablename: joined, on: on }),
+                        None => return None
+                    }
+                }
+                let predicate = if eat_word(p, "where") {
+                    match parse_predicate(cx, p) {
+                        None => return None,
+                        pred => pred
+                    }
+                } else {
+                    None
+                };
+                Some(Select {
+                    distinct: distinct,
+                    selector: cols,
+                    tablename: tablename,
+                    joins: joins,
+                    predicate: predicate,
+                    into: None
+                })
             },
             other => {
                 cx.span_err(p.last_span, format!("expected `from`, but found `{}`", other).as_slice());
@@ -246,17 +987,259 @@ fn parse_columns<'r>(cx: &ExtCtxt, p: &mut Parser<'r>) -> Option<SelectColumns>
     if p.eat(&token::BINOP(token::STAR)) {
         Some(AllColumns)
     } else {
-        let mut cols = Vec::new();
+        let mut items = Vec::new();
         loop {
-            let colname = p.parse_ident();
-            if colname.to_source().as_slice() == "from" {
-                cx.span_err(p.last_span, "expected `*` or column, but found `from`");
+            if is_word(p, "from") {
+                cx.span_err(p.span, "expected `*` or column, but found `from`");
                 return None
             }
-            cols.push(colname);
+            let expr = match parse_column_expr(cx, p) {
+                Some(expr) => expr,
+                None => return None
+            };
+            let alias = if eat_word(p, "as") {
+                Some(p.parse_ident())
+            } else {
+                None
+            };
+            items.push(SelectItem { expr: expr, alias: alias });
             if !p.eat(&token::COMMA) {
-                return Some(Columns(cols))
+                return Some(Columns(items))
             }
         }
     }
 }
+
+fn parse_column_expr<'r>(cx: &ExtCtxt, p: &mut Parser<'r>) -> Option<ColumnExpr> {
+    let mut lhs = match parse_column_product(cx, p) {
+        Some(expr) => expr,
+        None => return None
+    };
+    loop {
+        let op = match p.token {
+            token::BINOP(token::PLUS) => "+",
+            token::BINOP(token::MINUS) => "-",
+            _ => return Some(lhs)
+        };
+        p.bump();
+        match parse_column_product(cx, p) {
+            Some(rhs) => lhs = ArithExpr(box lhs, op, box rhs),
+            None => return None
+        }
+    }
+}
+
+fn parse_column_product<'r>(cx: &ExtCtxt, p: &mut Parser<'r>) -> Option<ColumnExpr> {
+    let mut lhs = match parse_column_concat(cx, p) {
+        Some(expr) => expr,
+        None => return None
+    };
+    loop {
+        let op = match p.token {
+            token::BINOP(token::STAR) => "*",
+            token::BINOP(token::SLASH) => "/",
+            token::BINOP(token::PERCENT) => "%",
+            _ => return Some(lhs)
+        };
+        p.bump();
+        match parse_column_concat(cx, p) {
+            Some(rhs) => lhs = ArithExpr(box lhs, op, box rhs),
+            None => return None
+        }
+    }
+}
+
+fn parse_column_concat<'r>(cx: &ExtCtxt, p: &mut Parser<'r>) -> Option<ColumnExpr> {
+    let mut lhs = match parse_column_atom(cx, p) {
+        Some(expr) => expr,
+        None => return None
+    };
+    while p.eat(&token::OROR) {
+        match parse_column_atom(cx, p) {
+            Some(rhs) => lhs = ConcatExpr(box lhs, box rhs),
+            None => return None
+        }
+    }
+    Some(lhs)
+}
+
+fn parse_column_atom<'r>(cx: &ExtCtxt, p: &mut Parser<'r>) -> Option<ColumnExpr> {
+    if p.eat(&token::LPAREN) {
+        let expr = parse_column_expr(cx, p);
+        p.expect(&token::RPAREN);
+        return expr
+    }
+
+    parse_operand(cx, p).map(|operand| {
+        match operand {
+            ParamOperand(value) => ValueExpr(value),
+            ColumnOperand(col) => ColumnRef(col)
+        }
+    })
+}
+
+fn parse_predicate<'r>(cx: &ExtCtxt, p: &mut Parser<'r>) -> Option<Predicate> {
+    let mut lhs = match parse_conjunction(cx, p) {
+        Some(pred) => pred,
+        None => return None
+    };
+    while eat_word(p, "or") {
+        match parse_conjunction(cx, p) {
+            Some(rhs) => lhs = OrPred(box lhs, box rhs),
+            None => return None
+        }
+    }
+    Some(lhs)
+}
+
+fn parse_conjunction<'r>(cx: &ExtCtxt, p: &mut Parser<'r>) -> Option<Predicate> {
+    let mut lhs = match parse_term(cx, p) {
+        Some(pred) => pred,
+        None => return None
+    };
+    while eat_word(p, "and") {
+        match parse_term(cx, p) {
+            Some(rhs) => lhs = AndPred(box lhs, box rhs),
+            None => return None
+        }
+    }
+    Some(lhs)
+}
+
+fn parse_term<'r>(cx: &ExtCtxt, p: &mut Parser<'r>) -> Option<Predicate> {
+    if eat_word(p, "not") {
+        return parse_term(cx, p).map(|pred| NotPred(box pred))
+    }
+
+    if p.eat(&token::LPAREN) {
+        let pred = parse_predicate(cx, p);
+        p.expect(&token::RPAREN);
+        return pred
+    }
+
+    let col = parse_column(p);
+    if eat_word(p, "is") {
+        let negated = eat_word(p, "not");
+        if !eat_word(p, "null") {
+            cx.span_err(p.span, "expected `null` after `is`");
+            return None
+        }
+        return Some(IsNullPred(col, negated))
+    }
+
+    if eat_word(p, "not") {
+        let pred = if eat_word(p, "in") {
+            parse_in(cx, p, col)
+        } else if is_word(p, "like") || is_word(p, "glob") {
+            parse_match(cx, p, col)
+        } else {
+            cx.span_err(p.span, "expected `in`, `like` or `glob` after `not`");
+            return None
+        };
+        return pred.map(|pred| NotPred(box pred))
+    }
+
+    if eat_word(p, "in") {
+        return parse_in(cx, p, col)
+    }
+
+    if is_word(p, "like") || is_word(p, "glob") {
+        return parse_match(cx, p, col)
+    }
+
+    let op = match p.token {
+        token::EQ | token::EQEQ => "=",
+        token::NE => "!=",
+        token::LT => "<",
+        token::LE => "<=",
+        token::GT => ">",
+        token::GE => ">=",
+        _ => {
+            let tok = p.this_token_to_str();
+            cx.span_err(p.span, format!("expected comparison operator, but found `{}`", tok).as_slice());
+            return None
+        }
+    };
+    p.bump();
+
+    parse_operand(cx, p).map(|operand| ComparePred(col, op, operand))
+}
+
+fn parse_match<'r>(cx: &ExtCtxt, p: &mut Parser<'r>, col: ColRef) -> Option<Predicate> {
+    let op = if eat_word(p, "like") { "LIKE" } else { p.bump(); "GLOB" };
+    parse_operand(cx, p).map(|operand| MatchPred(col, op, operand))
+}
+
+fn parse_in<'r>(cx: &ExtCtxt, p: &mut Parser<'r>, col: ColRef) -> Option<Predicate> {
+    p.expect(&token::LPAREN);
+    let pred = if eat_word(p, "select") {
+        parse_select(cx, p).and_then(|sub| {
+            match sub.selector {
+                Columns(ref cols) if cols.len() == 1 => (),
+                _ => {
+                    cx.span_err(p.last_span, "subquery in `in` must select exactly one column");
+                    return None
+                }
+            }
+            Some(InSelect(col, box sub))
+        })
+    } else if p.eat(&token::DOLLAR) {
+        let param = p.parse_ident();
+        Some(InList(col, cx.expr_ident(p.last_span, param)))
+    } else {
+        let tok = p.this_token_to_str();
+        cx.span_err(p.span, format!("expected `$param` or `select`, but found `{}`", tok).as_slice());
+        None
+    };
+    p.expect(&token::RPAREN);
+    pred
+}
+
+fn parse_column<'r>(p: &mut Parser<'r>) -> ColRef {
+    let first = p.parse_ident();
+    if p.eat(&token::DOT) {
+        ColRef { table: Some(first), name: p.parse_ident() }
+    } else {
+        ColRef { table: None, name: first }
+    }
+}
+
+fn parse_operand<'r>(cx: &ExtCtxt, p: &mut Parser<'r>) -> Option<Operand> {
+    if p.eat(&token::DOLLAR) {
+        let param = p.parse_ident();
+        return Some(ParamOperand(cx.expr_ident(p.last_span, param)))
+    }
+
+    match p.token {
+        token::IDENT(..) if !is_word(p, "true") && !is_word(p, "false") =>
+            return Some(ColumnOperand(parse_column(p))),
+        _ => ()
+    }
+
+    parse_literal(cx, p).map(|expr| ParamOperand(expr))
+}
+
+// Parses a literal into a Rust expression of the matching `SqlType`.
+// Character literals are accepted as strings, so `'!'` reads like SQL.
+fn parse_literal<'r>(cx: &ExtCtxt, p: &mut Parser<'r>) -> Option<@ast::Expr> {
+    let lit = p.parse_lit();
+    let sp = lit.span;
+    let expr = match lit.node {
+        ast::LitIntUnsuffixed(n) => cx.expr_lit(sp, ast::LitInt(n, ast::TyI)),
+        ast::LitFloatUnsuffixed(ref s) => cx.expr_lit(sp, ast::LitFloat(s.clone(), ast::TyF64)),
+        ast::LitInt(..) | ast::LitFloat(..) => cx.expr_lit(sp, lit.node.clone()),
+        ast::LitStr(ref s, _) => {
+            let s_expr = cx.expr_str(sp, s.clone());
+            quote_expr!(cx, $s_expr.to_string())
+        },
+        ast::LitChar(c) => {
+            let s_expr = cx.expr_str(sp, token::intern_and_get_ident(c.to_str().as_slice()));
+            quote_expr!(cx, $s_expr.to_string())
+        },
+        _ => {
+            cx.span_err(sp, "unsupported literal in SQL expression");
+            return None
+        }
+    };
+    Some(expr)
+}
@@ -8,7 +8,20 @@ extern crate sql_macro;
 extern crate sql;
 extern crate sqlite3;
 
-use sql::adapter::SqlAdapter;
+use std::cell::RefCell;
+use std::task;
+
+use sql::adapter::{SqlAdapter, UniqueViolation, CheckViolation};
+use sql::{IntValue, TextValue, NullValue};
+use sql::migrate::{Migrator, DroppedColumn, NotNullColumn, MissingColumn, MovedColumn, ChangedType,
+                   ChangedNullability, ChecksumMismatch, MigrationFailed};
+use sql::mock::MockAdapter;
+use sql::mysql::MysqlDialect;
+use sql::pool::{Pool, PoolConfig, CheckoutTimeout};
+use sql::postgres::PostgresDialect;
+use sql::query::{Query, OpEq, OpNe, OpGe, Desc};
+use sql::sqlite::{SqliteFactory, CacheStats, Attach};
+use sql::trace::{TracedAdapter, QueryLogger, QueryEvent, Succeeded, Failed, Aborted};
 
 #[sql_table]
 #[deriving(PartialEq, Show)]
@@ -17,6 +30,105 @@ pub struct TestTable {
     pub b: String
 }
 
+#[sql_table]
+#[deriving(PartialEq, Show)]
+pub struct OtherTable {
+    pub c: int,
+    pub d: String
+}
+
+#[sql_table]
+#[deriving(PartialEq, Show)]
+pub struct MigratedTable {
+    pub a: Option<int>,
+    pub b: String,
+    pub c: Option<String>
+}
+
+#[sql_table]
+#[sql_index(IndexedTable_a_b_idx, a, b, where = "a > 0")]
+#[deriving(PartialEq, Show)]
+pub struct IndexedTable {
+    pub a: int,
+    #[sql_index]
+    pub b: String
+}
+
+#[sql_table]
+#[sql_unique(a, b)]
+#[deriving(PartialEq, Show)]
+pub struct UniqueTable {
+    #[sql_unique]
+    pub id: int,
+    pub a: int,
+    pub b: Option<String>
+}
+
+#[sql_table]
+#[deriving(PartialEq, Show)]
+pub struct Author {
+    #[sql_primary_key]
+    pub id: int,
+    pub name: String
+}
+
+#[sql_table]
+#[deriving(PartialEq, Show)]
+pub struct Book {
+    #[sql_references(Author, on_delete = "cascade")]
+    pub author: Option<int>,
+    pub title: String
+}
+
+#[sql_table]
+#[deriving(PartialEq, Show)]
+pub struct DefaultTable {
+    pub id: int,
+    #[sql_default = "'anonymous'"]
+    pub name: Option<String>,
+    #[sql_default = "0"]
+    pub score: int
+}
+
+#[sql_table]
+#[deriving(PartialEq, Show)]
+pub struct TimestampedTable {
+    pub id: int,
+    #[sql_default = "CURRENT_TIMESTAMP"]
+    pub created: Option<String>
+}
+
+#[sql_table]
+#[sql_schema = "archive"]
+#[sql_index(ArchivedPost_title_idx, title)]
+#[deriving(PartialEq, Show)]
+pub struct ArchivedPost {
+    pub id: int,
+    pub title: String
+}
+
+#[sql_table]
+#[deriving(PartialEq, Show)]
+pub struct ScratchNote {
+    pub body: String
+}
+
+#[sql_table]
+#[sql_check = "low <= high"]
+#[deriving(PartialEq, Show)]
+pub struct CheckedTable {
+    #[sql_check = "low > 0"]
+    #[sql_check = "low < 1000"]
+    pub low: int,
+    pub high: int
+}
+
+#[deriving(PartialEq, Show)]
+pub struct Shout {
+    pub next: Option<int>,
+    pub shout: String
+}
+
 #[test]
 fn create_table_query_test() {
     assert_eq!(sql::create_table_query::<TestTable>(), "CREATE TABLE IF NOT EXISTS TestTable (a int, b text not null);".to_str())
@@ -24,12 +136,12 @@ fn create_table_query_test() {
 
 #[test]
 fn insert_query_test() {
-    assert_eq!(sql::insert_query::<TestTable>(), "INSERT INTO TestTable (a, b) VALUES (?, ?);")
+    assert_eq!(sql::insert_query::<TestTable>().as_slice(), "INSERT INTO TestTable (a, b) VALUES (?, ?);")
 }
 
 #[test]
 fn select_query_test() {
-    assert_eq!(sql::select_query::<TestTable>(), "SELECT * FROM TestTable;")
+    assert_eq!(sql::select_query::<TestTable>().as_slice(), "SELECT * FROM TestTable;")
 }
 
 #[test]
@@ -41,7 +153,7 @@ fn test_deriving_eq() {
 
 #[test]
 fn sql_macro_test() {
-    let db = sqlite3::open("sql_macro_test.sqlite3").unwrap();
+    let db = sqlite3::open(":memory:").unwrap();
     db.create_table_if_not_exists::<TestTable>();
 
     let records = vec![
@@ -49,7 +161,7 @@ fn sql_macro_test() {
         TestTable { a: Some(1), b: "Goodbye, world!".to_str() }
     ];
 
-    db.insert_many(records.iter());
+    db.insert_many(records.iter()).unwrap();
 
     let selector = sql!(select * from TestTable);
     let result:Vec<TestTable> = selector.fetch(&db).collect();
@@ -58,7 +170,7 @@ fn sql_macro_test() {
 
 #[test]
 fn select_columns_macro_test() {
-    let db = sqlite3::open("select_columns_macro_test.sqlite3").unwrap();
+    let db = sqlite3::open(":memory:").unwrap();
     db.create_table_if_not_exists::<TestTable>();
 
     let records = vec![
@@ -66,7 +178,7 @@ fn select_columns_macro_test() {
         TestTable { a: Some(1), b: "Goodbye, world!".to_str() }
     ];
 
-    db.insert_many(records.iter());
+    db.insert_many(records.iter()).unwrap();
 
     let selector = sql!(select a, b from TestTable);
     let result:Vec<(Option<int>, String)> = selector.fetch(&db).collect();
@@ -76,14 +188,14 @@ fn select_columns_macro_test() {
 
 #[test]
 fn insert_test() {
-    let db = sqlite3::open("insert_test.sqlite3").unwrap();
+    let db = sqlite3::open(":memory:").unwrap();
     let records = [
         TestTable { a: None, b: "Hello, world!".to_str() },
         TestTable { a: Some(1), b: "Goodbye, world!".to_str() }
     ];
 
     db.create_table_if_not_exists::<TestTable>();
-    db.insert_many(records.iter());
+    db.insert_many(records.iter()).unwrap();
     match db.prepare("SELECT * from TestTable;", &None) {
         Err(_) => fail!("{}", db.get_errmsg()),
         Ok(cursor) => {
@@ -117,7 +229,7 @@ fn insert_test() {
 
 #[test]
 fn select_test() {
-    let db = sqlite3::open("select_test.sqlite3").unwrap();
+    let db = sqlite3::open(":memory:").unwrap();
     db.create_table_if_not_exists::<TestTable>();
 
     match db.prepare("INSERT INTO TestTable VALUES (?, ?);", &None) {
@@ -168,3 +280,1029 @@ fn select_test() {
     let records: Vec<TestTable> = db.select_all().collect();
     assert_eq!(records, expected)
 }
+
+#[test]
+fn where_in_query_test() {
+    let wanted = vec![1i, 2, 3];
+    let selector = sql!(select * from TestTable where a in ($wanted));
+    assert_eq!(selector.query(), "SELECT * FROM TestTable WHERE a IN (?, ?, ?);");
+    assert_eq!(selector.params(), [sql::IntValue(1), sql::IntValue(2), sql::IntValue(3)].as_slice())
+}
+
+#[test]
+fn where_in_empty_query_test() {
+    let wanted: Vec<int> = vec![];
+    let selector = sql!(select * from TestTable where a in ($wanted));
+    assert_eq!(selector.query(), "SELECT * FROM TestTable WHERE 1 = 0;");
+    assert_eq!(selector.params(), [].as_slice());
+    let selector = sql!(select * from TestTable where a not in ($wanted));
+    assert_eq!(selector.query(), "SELECT * FROM TestTable WHERE NOT (1 = 0);");
+}
+
+#[test]
+fn where_none_param_query_test() {
+    let missing = None::<int>;
+    let selector = sql!(select * from TestTable where a = $missing);
+    assert_eq!(selector.query(), "SELECT * FROM TestTable WHERE a IS NULL;");
+    assert_eq!(selector.params(), [].as_slice());
+    let selector = sql!(select * from TestTable where a != $missing);
+    assert_eq!(selector.query(), "SELECT * FROM TestTable WHERE a IS NOT NULL;");
+    let present = Some(1i);
+    let selector = sql!(select * from TestTable where a = $present);
+    assert_eq!(selector.query(), "SELECT * FROM TestTable WHERE a = ?;");
+    assert_eq!(selector.params(), [sql::IntValue(1)].as_slice())
+}
+
+#[test]
+fn where_in_macro_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.create_table_if_not_exists::<TestTable>();
+
+    let records = vec![
+        TestTable { a: None, b: "Hello, world!".to_str() },
+        TestTable { a: Some(1), b: "Goodbye, world!".to_str() },
+        TestTable { a: Some(2), b: "Hello again, world!".to_str() }
+    ];
+
+    db.insert_many(records.iter()).unwrap();
+
+    let wanted = vec![2i, 3];
+    let selector = sql!(select b from TestTable where a in ($wanted));
+    let result:Vec<(String,)> = selector.fetch(&db).collect();
+    assert_eq!(result, vec![("Hello again, world!".to_str(),)]);
+
+    let selector = sql!(select b from TestTable where a not in ($wanted) and a = 1);
+    let result:Vec<(String,)> = selector.fetch(&db).collect();
+    assert_eq!(result, vec![("Goodbye, world!".to_str(),)])
+}
+
+#[test]
+fn where_in_subquery_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.create_table_if_not_exists::<TestTable>();
+    db.create_table_if_not_exists::<OtherTable>();
+
+    let records = vec![
+        TestTable { a: Some(1), b: "Hello, world!".to_str() },
+        TestTable { a: Some(2), b: "Goodbye, world!".to_str() }
+    ];
+    let others = vec![
+        OtherTable { c: 1, d: "one".to_str() },
+        OtherTable { c: 2, d: "two".to_str() }
+    ];
+
+    db.insert_many(records.iter()).unwrap();
+    db.insert_many(others.iter()).unwrap();
+
+    let name = "two".to_str();
+    let selector = sql!(select * from TestTable where a in (select c from OtherTable where d = $name));
+    assert_eq!(selector.query(), "SELECT * FROM TestTable WHERE a IN (SELECT c FROM OtherTable WHERE d = ?);");
+    let result:Vec<TestTable> = selector.fetch(&db).collect();
+    assert_eq!(result, vec![TestTable { a: Some(2), b: "Goodbye, world!".to_str() }])
+}
+
+#[test]
+fn select_distinct_macro_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.create_table_if_not_exists::<TestTable>();
+
+    let records = vec![
+        TestTable { a: Some(1), b: "Hello, world!".to_str() },
+        TestTable { a: Some(2), b: "Hello, world!".to_str() },
+        TestTable { a: Some(2), b: "Hello, world!".to_str() }
+    ];
+
+    db.insert_many(records.iter()).unwrap();
+
+    let selector = sql!(select distinct b from TestTable);
+    assert_eq!(selector.query(), "SELECT DISTINCT b FROM TestTable;");
+    let result:Vec<(String,)> = selector.fetch(&db).collect();
+    assert_eq!(result, vec![("Hello, world!".to_str(),)]);
+
+    let selector = sql!(select distinct * from TestTable);
+    assert_eq!(selector.query(), "SELECT DISTINCT * FROM TestTable;");
+    let result:Vec<TestTable> = selector.fetch(&db).collect();
+    assert_eq!(result, vec![
+        TestTable { a: Some(1), b: "Hello, world!".to_str() },
+        TestTable { a: Some(2), b: "Hello, world!".to_str() }
+    ])
+}
+
+#[test]
+fn runtime_query_test() {
+    let query = Query::<TestTable>::new()
+        .filter("a", OpGe, &1i)
+        .filter("b", OpEq, &"Hello, world!".to_str())
+        .order_by("a", Desc)
+        .limit(10);
+    let buf = query.build().unwrap();
+    assert_eq!(buf.query(), "SELECT * FROM TestTable WHERE a >= ? AND b = ? ORDER BY a DESC LIMIT 10;");
+    assert_eq!(buf.params(), [sql::IntValue(1), sql::TextValue("Hello, world!".to_str())].as_slice());
+
+    let query = Query::<TestTable>::new()
+        .filter("a", OpEq, &None::<int>)
+        .filter("b", OpNe, &"Hello, world!".to_str());
+    let buf = query.build().unwrap();
+    assert_eq!(buf.query(), "SELECT * FROM TestTable WHERE a IS NULL AND b != ?;");
+    assert_eq!(buf.params(), [sql::TextValue("Hello, world!".to_str())].as_slice());
+
+    let query = Query::<TestTable>::new()
+        .filter("a; DROP TABLE TestTable", OpEq, &1i)
+        .order_by("c", Desc);
+    assert_eq!(query.build().map(|buf| buf.query().to_str()),
+               Err("`a; DROP TABLE TestTable` is not a column of table `TestTable`".to_str()));
+    let query = Query::<TestTable>::new().filter("a", OpGe, &None::<int>);
+    assert_eq!(query.build().map(|buf| buf.query().to_str()),
+               Err("cannot compare column `a` with NULL using >=".to_str()))
+}
+
+#[test]
+fn runtime_query_fetch_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.create_table_if_not_exists::<TestTable>();
+
+    let records = vec![
+        TestTable { a: Some(1), b: "Hello, world!".to_str() },
+        TestTable { a: Some(2), b: "Goodbye, world!".to_str() },
+        TestTable { a: Some(3), b: "Hello again, world!".to_str() }
+    ];
+
+    db.insert_many(records.iter()).unwrap();
+
+    let result:Vec<TestTable> = Query::<TestTable>::new()
+        .filter("a", OpGe, &2i)
+        .order_by("a", Desc)
+        .limit(1)
+        .fetch(&db)
+        .unwrap()
+        .collect();
+    assert_eq!(result, vec![TestTable { a: Some(3), b: "Hello again, world!".to_str() }])
+}
+
+#[test]
+fn where_null_and_like_macro_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.create_table_if_not_exists::<TestTable>();
+
+    let records = vec![
+        TestTable { a: None, b: "Hello, world!".to_str() },
+        TestTable { a: Some(1), b: "Goodbye, world!".to_str() }
+    ];
+
+    db.insert_many(records.iter()).unwrap();
+
+    let selector = sql!(select b from TestTable where a is null);
+    assert_eq!(selector.query(), "SELECT b FROM TestTable WHERE a IS NULL;");
+    let result:Vec<(String,)> = selector.fetch(&db).collect();
+    assert_eq!(result, vec![("Hello, world!".to_str(),)]);
+
+    let selector = sql!(select b from TestTable where a is not null);
+    let result:Vec<(String,)> = selector.fetch(&db).collect();
+    assert_eq!(result, vec![("Goodbye, world!".to_str(),)]);
+
+    let selector = sql!(select a from TestTable where b like "Good%");
+    assert_eq!(selector.query(), "SELECT a FROM TestTable WHERE b LIKE ?;");
+    let result:Vec<(Option<int>,)> = selector.fetch(&db).collect();
+    assert_eq!(result, vec![(Some(1),)]);
+
+    let selector = sql!(select a from TestTable where b not glob "Good*");
+    let result:Vec<(Option<int>,)> = selector.fetch(&db).collect();
+    assert_eq!(result, vec![(None,)])
+}
+
+#[test]
+fn select_expressions_macro_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.create_table_if_not_exists::<TestTable>();
+
+    let records = vec![
+        TestTable { a: None, b: "Hello, world".to_str() },
+        TestTable { a: Some(1), b: "Goodbye, world".to_str() }
+    ];
+
+    db.insert_many(records.iter()).unwrap();
+
+    let selector = sql!(select a + 1 as next, b || '!' as shout from TestTable);
+    assert_eq!(selector.query(), "SELECT a + ? AS next, b || ? AS shout FROM TestTable;");
+    let result:Vec<(Option<int>, String)> = selector.fetch(&db).collect();
+    assert_eq!(result, vec![(None, "Hello, world!".to_str()), (Some(2), "Goodbye, world!".to_str())]);
+
+    let selector = sql!(select a + 1 as next, b || '!' as shout from TestTable where a is not null into Shout);
+    let result:Vec<Shout> = selector.fetch(&db).collect();
+    assert_eq!(result, vec![Shout { next: Some(2), shout: "Goodbye, world!".to_str() }]);
+
+    // Dividing by zero yields NULL, so the quotient of columns which are not
+    // null is nullable.
+    db.create_table_if_not_exists::<DefaultTable>();
+    db.insert_many([DefaultTable { id: 6, name: None, score: 3 },
+                    DefaultTable { id: 6, name: None, score: 0 }].iter()).unwrap();
+    let selector = sql!(select id / score, id % score from DefaultTable);
+    let result:Vec<(Option<int>, Option<int>)> = selector.fetch(&db).collect();
+    assert_eq!(result, vec![(Some(2), Some(0)), (None, None)])
+}
+
+#[test]
+fn migrate_add_column_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.execute("CREATE TABLE IF NOT EXISTS MigratedTable (a int, b text not null);");
+    db.execute("INSERT INTO MigratedTable VALUES (1, 'Hello, world!');");
+
+    let report = db.migrate::<MigratedTable>().unwrap();
+    assert_eq!(report.queries, vec!["ALTER TABLE MigratedTable ADD COLUMN c text;".to_str()]);
+    assert_eq!(db.migrate::<MigratedTable>().unwrap().queries, vec![]);
+
+    let result:Vec<MigratedTable> = db.select_all().collect();
+    assert_eq!(result, vec![MigratedTable { a: Some(1), b: "Hello, world!".to_str(), c: None }])
+}
+
+#[test]
+fn migrate_refuse_destructive_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.execute("CREATE TABLE IF NOT EXISTS TestTable (a int, b text not null, old int);");
+    db.execute("CREATE TABLE IF NOT EXISTS MigratedTable (a int);");
+
+    let err = db.migrate::<TestTable>().unwrap_err();
+    assert_eq!(err.table, "TestTable".to_str());
+    assert_eq!(err.problems, vec![DroppedColumn("old".to_str())]);
+
+    let err = db.migrate::<MigratedTable>().unwrap_err();
+    assert_eq!(err.problems, vec![NotNullColumn("b".to_str())])
+}
+
+fn seed_versioned_table(db: &sqlite3::Database) -> Result<(), String> {
+    db.insert_many([OtherTable { c: 1, d: "one".to_str() }].iter()).map_err(|e| e.to_str())
+}
+
+fn failing_migration(_: &sqlite3::Database) -> Result<(), String> {
+    Err("not today".to_str())
+}
+
+fn seed_count(count: &int, db: &sqlite3::Database) -> Result<(), String> {
+    let records: Vec<OtherTable> = range(0, *count).map(|c| OtherTable { c: c, d: c.to_str() }).collect();
+    db.insert_many(records.iter()).map_err(|e| e.to_str())
+}
+
+#[test]
+fn migrator_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    let migrator = Migrator::new()
+        .add("001_create", "CREATE TABLE OtherTable (c int not null, d text not null);")
+        .add("002_seed", seed_versioned_table);
+
+    assert_eq!(migrator.run(&db), Ok(vec!["001_create".to_str(), "002_seed".to_str()]));
+    assert_eq!(migrator.run(&db), Ok(vec![]));
+
+    let result:Vec<OtherTable> = db.select_all().collect();
+    assert_eq!(result, vec![OtherTable { c: 1, d: "one".to_str() }]);
+
+    let edited = Migrator::new()
+        .add("001_create", "CREATE TABLE OtherTable (c int, d text);");
+    assert_eq!(edited.run(&db), Err(ChecksumMismatch("001_create".to_str())));
+
+    let failing = Migrator::new()
+        .add("003_index", "CREATE INDEX OtherTable_d ON OtherTable (d);")
+        .add("004_fail", failing_migration);
+    assert_eq!(failing.run(&db), Err(MigrationFailed("004_fail".to_str(), "not today".to_str())));
+    let applied: Vec<(String,)> = unsafe {
+        db.select_columns("SELECT name FROM _sql_migrations WHERE name = '003_index';", []).collect()
+    };
+    assert_eq!(applied, vec![]);
+
+    let invalid = Migrator::new()
+        .add("003_index", "CREATE INDEX OtherTable_d ON OtherTable (d);")
+        .add("004_invalid", "CREATE TABLE;");
+    match invalid.run(&db) {
+        Err(MigrationFailed(name, _)) => assert_eq!(name, "004_invalid".to_str()),
+        result => fail!("unexpected result {}", result)
+    }
+    let indexes: Vec<(String,)> = unsafe {
+        db.select_columns("SELECT name FROM sqlite_master WHERE name = 'OtherTable_d';", []).collect()
+    };
+    assert_eq!(indexes, vec![]);
+
+    let seeded = Migrator::new()
+        .add("005_seed", (2i, seed_count as fn(&int, &sqlite3::Database) -> Result<(), String>));
+    assert_eq!(seeded.run(&db), Ok(vec!["005_seed".to_str()]));
+    let result:Vec<OtherTable> = db.select_all().collect();
+    assert_eq!(result.len(), 3);
+
+    db.execute("INSERT INTO _sql_migrations VALUES ('006_short', '0123456789abcdef', '2014-07-01T00:00:00Z');");
+    let short = Migrator::new()
+        .add("006_short", "SELECT 1;");
+    assert_eq!(short.run(&db), Err(ChecksumMismatch("006_short".to_str())))
+}
+
+#[test]
+fn create_index_queries_test() {
+    assert_eq!(sql::create_index_queries::<IndexedTable>(), vec![
+        "CREATE INDEX IF NOT EXISTS IndexedTable_b_idx ON IndexedTable (b);".to_str(),
+        "CREATE INDEX IF NOT EXISTS IndexedTable_a_b_idx ON IndexedTable (a, b) WHERE a > 0;".to_str()
+    ]);
+    assert_eq!(sql::create_index_queries::<TestTable>(), vec![])
+}
+
+#[test]
+fn create_index_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.create_table_if_not_exists::<IndexedTable>();
+
+    let indexes: Vec<(String,)> = unsafe {
+        db.select_columns("SELECT name FROM sqlite_master WHERE type = 'index' ORDER BY name;", []).collect()
+    };
+    assert_eq!(indexes, vec![("IndexedTable_a_b_idx".to_str(),), ("IndexedTable_b_idx".to_str(),)])
+}
+
+#[test]
+fn unique_create_table_query_test() {
+    assert_eq!(sql::create_table_query::<UniqueTable>(),
+               "CREATE TABLE IF NOT EXISTS UniqueTable (id int not null UNIQUE, a int not null, b text, UNIQUE (a, b));".to_str())
+}
+
+#[test]
+fn unique_violation_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.create_table_if_not_exists::<UniqueTable>();
+
+    let records = [
+        UniqueTable { id: 1, a: 1, b: Some("Hello, world!".to_str()) },
+        UniqueTable { id: 2, a: 1, b: Some("Goodbye, world!".to_str()) }
+    ];
+    assert_eq!(db.insert_many(records.iter()), Ok(()));
+
+    let duplicate_id = [UniqueTable { id: 1, a: 2, b: None }];
+    assert_eq!(db.insert_many(duplicate_id.iter()),
+               Err(UniqueViolation { table: "UniqueTable".to_str(), columns: vec!["id".to_str()] }));
+
+    let duplicate_group = [UniqueTable { id: 3, a: 1, b: Some("Hello, world!".to_str()) }];
+    assert_eq!(db.insert_many(duplicate_group.iter()),
+               Err(UniqueViolation { table: "UniqueTable".to_str(), columns: vec!["a".to_str(), "b".to_str()] }));
+
+    // The records before the failing one are rolled back with it.
+    let partial = [UniqueTable { id: 4, a: 4, b: None }, UniqueTable { id: 1, a: 5, b: None }];
+    assert!(db.insert_many(partial.iter()).is_err());
+    let result: Vec<UniqueTable> = db.select_all().collect();
+    assert_eq!(result.as_slice(), records.as_slice())
+}
+
+#[test]
+fn references_create_table_query_test() {
+    assert_eq!(sql::create_table_query::<Author>(),
+               "CREATE TABLE IF NOT EXISTS Author (id int not null PRIMARY KEY, name text not null);".to_str());
+    assert_eq!(sql::create_table_query::<Book>(),
+               "CREATE TABLE IF NOT EXISTS Book (author int REFERENCES Author(id) ON DELETE CASCADE, title text not null);".to_str())
+}
+
+#[test]
+fn references_test() {
+    let db = sql::sqlite::open_in_memory().unwrap();
+    db.create_table_if_not_exists::<Author>();
+    db.create_table_if_not_exists::<Book>();
+
+    db.insert_many([Author { id: 1, name: "Jane".to_str() }].iter()).unwrap();
+    db.insert_many([Book { author: Some(1), title: "Emma".to_str() }].iter()).unwrap();
+    assert!(db.insert_many([Book { author: Some(2), title: "Ulysses".to_str() }].iter()).is_err());
+
+    db.execute("DELETE FROM Author WHERE id = 1;");
+    let books: Vec<Book> = db.select_all().collect();
+    assert_eq!(books, vec![])
+}
+
+#[test]
+fn default_queries_test() {
+    assert_eq!(sql::create_table_query::<DefaultTable>(),
+               "CREATE TABLE IF NOT EXISTS DefaultTable (id int not null, name text DEFAULT 'anonymous', score int not null DEFAULT 0);".to_str());
+    assert_eq!(sql::insert_query::<DefaultTable>().as_slice(),
+               "INSERT INTO DefaultTable (id, name, score) VALUES (?, COALESCE(?, 'anonymous'), ?);")
+}
+
+#[test]
+fn default_insert_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.create_table_if_not_exists::<DefaultTable>();
+
+    let records = [
+        DefaultTable { id: 1, name: None, score: 3 },
+        DefaultTable { id: 2, name: Some("Jane".to_str()), score: 5 }
+    ];
+    db.insert_many(records.iter()).unwrap();
+
+    let result: Vec<DefaultTable> = db.select_all().collect();
+    assert_eq!(result, vec![
+        DefaultTable { id: 1, name: Some("anonymous".to_str()), score: 3 },
+        DefaultTable { id: 2, name: Some("Jane".to_str()), score: 5 }
+    ])
+}
+
+#[test]
+fn migrate_default_column_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.execute("CREATE TABLE IF NOT EXISTS DefaultTable (id int not null, name text DEFAULT 'anonymous');");
+
+    let report = db.migrate::<DefaultTable>().unwrap();
+    assert_eq!(report.queries, vec!["ALTER TABLE DefaultTable ADD COLUMN score int not null DEFAULT 0;".to_str()]);
+    assert_eq!(report.defaults_not_added, vec![])
+}
+
+#[test]
+fn migrate_timestamp_column_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.execute("CREATE TABLE IF NOT EXISTS TimestampedTable (id int not null);");
+    db.execute("INSERT INTO TimestampedTable VALUES (1);");
+
+    let report = db.migrate::<TimestampedTable>().unwrap();
+    assert_eq!(report.queries, vec!["ALTER TABLE TimestampedTable ADD COLUMN created text;".to_str(),
+                                    "UPDATE TimestampedTable SET created = CURRENT_TIMESTAMP;".to_str()]);
+    assert_eq!(report.defaults_not_added, vec!["created".to_str()]);
+
+    let result: Vec<TimestampedTable> = db.select_all().collect();
+    assert!(result.iter().all(|record| record.created.is_some()))
+}
+
+#[test]
+fn check_create_table_query_test() {
+    assert_eq!(sql::create_table_query::<CheckedTable>(),
+               "CREATE TABLE IF NOT EXISTS CheckedTable (low int not null CONSTRAINT CheckedTable_low_check CHECK (low > 0) CONSTRAINT CheckedTable_low_check_2 CHECK (low < 1000), high int not null, CONSTRAINT CheckedTable_check_1 CHECK (low <= high));".to_str())
+}
+
+#[test]
+fn check_violation_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.create_table_if_not_exists::<CheckedTable>();
+
+    assert_eq!(db.insert_many([CheckedTable { low: 1, high: 2 }].iter()), Ok(()));
+    assert_eq!(db.insert_many([CheckedTable { low: 0, high: 2 }].iter()),
+               Err(CheckViolation { table: "CheckedTable".to_str(), constraint: "CheckedTable_low_check".to_str() }));
+    assert_eq!(db.insert_many([CheckedTable { low: 1000, high: 2000 }].iter()),
+               Err(CheckViolation { table: "CheckedTable".to_str(), constraint: "CheckedTable_low_check_2".to_str() }));
+    assert_eq!(db.insert_many([CheckedTable { low: 3, high: 2 }].iter()),
+               Err(CheckViolation { table: "CheckedTable".to_str(), constraint: "CheckedTable_check_1".to_str() }))
+}
+
+#[test]
+fn constraint_error_test() {
+    use sql::adapter::{constraint_error, SqlFailure};
+    let check = |constraint: &str| CheckViolation { table: "CheckedTable".to_str(), constraint: constraint.to_str() };
+    assert_eq!(constraint_error("CheckedTable", "CHECK constraint failed: CheckedTable_low_check"),
+               check("CheckedTable_low_check"));
+    assert_eq!(constraint_error("CheckedTable", "constraint CheckedTable_low_check failed"),
+               check("CheckedTable_low_check"));
+    assert_eq!(constraint_error("CheckedTable", "constraint failed"), check(""));
+    assert_eq!(constraint_error("UniqueTable", "columns a, b are not unique"),
+               UniqueViolation { table: "UniqueTable".to_str(), columns: vec!["a".to_str(), "b".to_str()] });
+    assert_eq!(constraint_error("Book", "foreign key constraint failed"),
+               SqlFailure("foreign key constraint failed".to_str()))
+}
+
+#[test]
+fn drop_and_delete_table_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.drop_table_if_exists::<TestTable>();
+    assert!(!db.table_exists::<TestTable>());
+
+    db.create_table_if_not_exists::<TestTable>();
+    assert!(db.table_exists::<TestTable>());
+
+    db.insert_many([TestTable { a: Some(1), b: "Hello, world!".to_str() }].iter()).unwrap();
+    db.delete_all::<TestTable>();
+    let result: Vec<TestTable> = db.select_all().collect();
+    assert_eq!(result, vec![]);
+
+    db.drop_table::<TestTable>();
+    assert!(!db.table_exists::<TestTable>())
+}
+
+#[test]
+fn codegen_test() {
+    let db = sql::sqlite::TempDatabase::new().unwrap();
+    db.execute("CREATE TABLE Legacy (\
+                    id INTEGER PRIMARY KEY, \
+                    name VARCHAR(20) NOT NULL DEFAULT 'none', \
+                    score DOUBLE);");
+    db.execute("CREATE TABLE Unsupported (id INTEGER, data BLOB, created DATETIME, type TEXT);");
+
+    assert_eq!(sql::codegen::table_names(&*db), vec!["Legacy".to_str(), "Unsupported".to_str()]);
+
+    let columns = db.table_columns("Legacy");
+    assert_eq!(sql::codegen::table_struct("Legacy", columns.as_slice()),
+               Ok("#[sql_table]\n\
+                   pub struct Legacy {\n    \
+                       #[sql_primary_key]\n    \
+                       pub id: Option<int>,\n    \
+                       #[sql_default = \"'none'\"]\n    \
+                       pub name: String,\n    \
+                       pub score: Option<f64>\n\
+                   }\n".to_str()));
+
+    // Columns after an unreadable one would be misread, so the table is refused.
+    let columns = db.table_columns("Unsupported");
+    assert_eq!(sql::codegen::table_struct("Unsupported", columns.as_slice()),
+               Err("cannot generate Unsupported: column `data` has unsupported type `BLOB`, \
+                    column `created` has unsupported type `DATETIME`, \
+                    column name `type` is not a Rust identifier".to_str()))
+}
+
+#[test]
+fn verify_schema_test() {
+    let db = sqlite3::open(":memory:").unwrap();
+    db.create_table_if_not_exists::<TestTable>();
+    assert_eq!(db.verify_schema::<TestTable>(), vec![]);
+
+    db.execute("CREATE TABLE IF NOT EXISTS MigratedTable (b text, a real, old int);");
+    assert_eq!(db.verify_schema::<MigratedTable>(), vec![
+        MovedColumn("b".to_str(), 1, 0),
+        ChangedNullability("b".to_str(), false),
+        MovedColumn("a".to_str(), 0, 1),
+        ChangedType("a".to_str(), "int".to_str(), "real".to_str()),
+        DroppedColumn("old".to_str()),
+        MissingColumn("c".to_str())
+    ])
+}
+
+#[test]
+fn postgres_queries_test() {
+    assert_eq!(sql::postgres::numbered_placeholders("SELECT a FROM T WHERE b = '?' AND c = ?;").as_slice(),
+               "SELECT a FROM T WHERE b = '?' AND c = $1;")
+}
+
+// Needs a PostgreSQL server accepting the `postgres` user on localhost.
+#[test]
+#[ignore]
+fn postgres_test() {
+    let db = sql::postgres::connect("127.0.0.1", 5432, "postgres", None, "postgres").unwrap();
+    db.drop_table_if_exists::<TestTable>();
+    db.create_table_if_not_exists::<TestTable>();
+    assert!(db.table_exists::<TestTable>());
+
+    let records = vec![
+        TestTable { a: Some(1), b: "Hello, world!".to_str() },
+        TestTable { a: None, b: "Foo".to_str() }
+    ];
+    db.insert_many(records.iter()).unwrap();
+
+    let result: Vec<TestTable> = db.select_all().collect();
+    assert_eq!(result, records);
+
+    let b = "Foo".to_str();
+    let rows: Vec<(Option<int>,)> = sql!(select a from TestTable where b = $b).fetch(&db).collect();
+    assert_eq!(rows, vec![(None,)]);
+
+    db.drop_table::<TestTable>()
+}
+
+#[test]
+fn dialect_queries_test() {
+    assert_eq!(sql::dialect::create_table_query::<DefaultTable>(&PostgresDialect, None),
+               "CREATE TABLE IF NOT EXISTS \"defaulttable\" (\"id\" bigint not null, \"name\" text DEFAULT 'anonymous', \
+                \"score\" bigint not null DEFAULT 0);".to_str());
+    assert_eq!(sql::dialect::insert_query::<DefaultTable>(&PostgresDialect, None),
+               "INSERT INTO \"defaulttable\" (\"id\", \"name\", \"score\") \
+                VALUES ($1, COALESCE($2, 'anonymous'), $3);".to_str());
+
+    assert_eq!(sql::dialect::create_table_query::<TestTable>(&MysqlDialect, None),
+               "CREATE TABLE IF NOT EXISTS `TestTable` (`a` bigint, `b` text not null);".to_str());
+    assert_eq!(sql::dialect::create_table_query::<UniqueTable>(&MysqlDialect, None),
+               "CREATE TABLE IF NOT EXISTS `UniqueTable` (`id` bigint not null UNIQUE, `a` bigint not null, \
+                `b` varchar(255), UNIQUE (`a`, `b`));".to_str());
+    assert_eq!(sql::dialect::create_index_queries::<IndexedTable>(&MysqlDialect, None), vec![
+        "CREATE INDEX `IndexedTable_b_idx` ON `IndexedTable` (`b`);".to_str(),
+        "CREATE INDEX `IndexedTable_a_b_idx` ON `IndexedTable` (`a`, `b`);".to_str()
+    ]);
+    assert_eq!(sql::dialect::select_query::<TestTable>(&MysqlDialect, None), "SELECT * FROM `TestTable`;".to_str())
+}
+
+// Needs a MariaDB or MySQL server accepting `root` without a password on
+// localhost, with a `test` database.
+#[test]
+#[ignore]
+fn mysql_test() {
+    let db = sql::mysql::connect("127.0.0.1", 3306, "root", None, "test").unwrap();
+    db.drop_table_if_exists::<UniqueTable>();
+    db.create_table_if_not_exists::<UniqueTable>();
+    assert!(db.table_exists::<UniqueTable>());
+
+    let records = vec![
+        UniqueTable { id: 1, a: 1, b: Some("Hello, world!".to_str()) },
+        UniqueTable { id: 2, a: 1, b: None }
+    ];
+    db.insert_many(records.iter()).unwrap();
+    assert_eq!(db.insert_many([UniqueTable { id: 1, a: 2, b: None }].iter()),
+               Err(UniqueViolation { table: "UniqueTable".to_str(), columns: vec!["id".to_str()] }));
+
+    let result: Vec<UniqueTable> = db.select_all().collect();
+    assert_eq!(result, records);
+
+    let id = 2i;
+    let rows: Vec<(Option<String>,)> = sql!(select b from UniqueTable where id = $id).fetch(&db).collect();
+    assert_eq!(rows, vec![(None,)]);
+
+    db.drop_table::<UniqueTable>()
+}
+
+#[test]
+fn digest_test() {
+    let hex = |digest: Vec<u8>| sql::digest::to_hex(digest.as_slice());
+    // RFC 1321
+    assert_eq!(hex(sql::digest::md5([])), "d41d8cd98f00b204e9800998ecf8427e".to_str());
+    assert_eq!(hex(sql::digest::md5("a".as_bytes())), "0cc175b9c0f1b6a831c399e269772661".to_str());
+    assert_eq!(hex(sql::digest::md5("abc".as_bytes())), "900150983cd24fb0d6963f7d28e17f72".to_str());
+    assert_eq!(hex(sql::digest::md5("message digest".as_bytes())), "f96b697d7cb7938d525a2f31aaf161d0".to_str());
+    assert_eq!(hex(sql::digest::md5("abcdefghijklmnopqrstuvwxyz".as_bytes())),
+               "c3fcd3d76192e4007dfb496cca67e13b".to_str());
+    let digits = "12345678901234567890123456789012345678901234567890123456789012345678901234567890";
+    assert_eq!(hex(sql::digest::md5(digits.as_bytes())), "57edf4a22be3c955ac49da2e2107b67a".to_str());
+    // RFC 4231, test case 2
+    assert_eq!(hex(sql::digest::hmac_sha256("Jefe".as_bytes(), "what do ya want for nothing?".as_bytes())),
+               "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843".to_str());
+    assert_eq!(hex(sql::digest::pbkdf2_sha256("password".as_bytes(), "salt".as_bytes(), 1)),
+               "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b".to_str());
+    assert_eq!(hex(sql::digest::pbkdf2_sha256("password".as_bytes(), "salt".as_bytes(), 2)),
+               "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43".to_str());
+
+    let long = "abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq".as_bytes();
+    assert_eq!(hex(sql::digest::sha1([])), "da39a3ee5e6b4b0d3255bfef95601890afd80709".to_str());
+    assert_eq!(hex(sql::digest::sha1("abc".as_bytes())), "a9993e364706816aba3e25717850c26c9cd0d89d".to_str());
+    assert_eq!(hex(sql::digest::sha1(long)), "84983e441c3bd26ebaae4aa1f95129e5e54670f1".to_str());
+    assert_eq!(hex(sql::digest::sha256([])),
+               "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_str());
+    assert_eq!(hex(sql::digest::sha256("abc".as_bytes())),
+               "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_str());
+    assert_eq!(hex(sql::digest::sha256(long)),
+               "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1".to_str());
+
+    let scramble: Vec<u8> = range(1u8, 21).collect();
+    assert_eq!(hex(sql::mysql::native_password(Some("password"), scramble.as_slice())),
+               "c17d6009a5cb47e59f7483fcf05553bbbf7dd0d6".to_str());
+    assert_eq!(hex(sql::mysql::sha2_password(Some("password"), scramble.as_slice())),
+               "f7ab1c623a6e98dceab35e926290e5746a3141116115f4dd8ccca994393eccdd".to_str());
+    assert_eq!(sql::mysql::native_password(None, scramble.as_slice()), vec![]);
+
+    // A 1024-bit key, with the ciphertext checked against OpenSSL's OAEP decryption.
+    let pem = "-----BEGIN PUBLIC KEY-----
+MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQC6tsu2/Bj0FnYeYtBp2qXjixv2
+2TR5dtxvJYz+t9fy774tK5ZSBTngSm1Da0byp7gRpa4NqRMCgUNhDyMM2ysUlg2p
+Lc3yvp37HipLT23U1yySx6EdkQ2M5SicY+xuvTgSc58qiYnIuM6sdaiayPYrr7hy
+HFaFv/6cYgiPgZeReQIDAQAB
+-----END PUBLIC KEY-----";
+    let (modulus, exponent) = sql::mysql::rsa_public_key(pem).unwrap();
+    assert_eq!(hex(modulus), "00bab6cbb6fc18f416761e62d069daa5e38b1bf6d9347976dc6f258cfeb7d7f2ef\
+                              be2d2b96520539e04a6d436b46f2a7b811a5ae0da913028143610f230cdb2b14\
+                              960da92dcdf2be9dfb1e2a4b4f6dd4d72c92c7a11d910d8ce5289c63ec6ebd38\
+                              12739f2a8989c8b8ceac75a89ac8f62bafb8721c5685bffe9c62088f81979179".to_str());
+    assert_eq!(exponent, vec![1, 0, 1]);
+    assert_eq!(sql::mysql::rsa_public_key("-----BEGIN PUBLIC KEY-----\nMIGfMA0G\n-----END PUBLIC KEY-----"), None);
+    let seed: Vec<u8> = range(0u8, 20).collect();
+    let encrypted = sql::mysql::rsa_encrypt(pem, "secret\0".as_bytes(), seed.as_slice());
+    assert_eq!(encrypted.map(|encrypted| hex(encrypted)),
+               Some("82ef27e64bf2e013a24f696634f078f9f0caa63293d36b165da9f922fcf42add\
+                     4697b5709e25883ba2ce8efa0d7fed2906bd54d41b347e1e629f93092fc129ff\
+                     662e190acf536a462908d864389e6d474281dd2de1fdd9c4db06c19726dc7c0b\
+                     b5ba32f378d90865b616796e7cb5b26f6579175cf05b8381511260bc4969b3f0".to_str()));
+    assert_eq!(sql::mysql::rsa_encrypt(pem, [0u8, ..87], seed.as_slice()), None);
+}
+
+#[test]
+fn mock_adapter_test() {
+    let db = MockAdapter::new();
+    db.create_table_if_not_exists::<TestTable>();
+    assert!(db.table_exists::<TestTable>());
+    db.insert_many([TestTable { a: None, b: "Foo".to_str() }].iter()).unwrap();
+
+    db.push_rows(vec![vec![IntValue(1), TextValue("Hello, world!".to_str())]]);
+    let result: Vec<TestTable> = db.select_all().collect();
+    assert_eq!(result, vec![TestTable { a: Some(1), b: "Hello, world!".to_str() }]);
+
+    let wanted = 1i;
+    let rows: Vec<(String,)> = sql!(select b from TestTable where a = $wanted).fetch(&db).collect();
+    assert_eq!(rows, vec![]);
+
+    db.expect_query("CREATE TABLE IF NOT EXISTS TestTable (a int, b text not null);", []);
+    db.expect_query("INSERT INTO TestTable (a, b) VALUES (?, ?);", [NullValue, TextValue("Foo".to_str())]);
+    db.expect_query("SELECT * FROM TestTable;", []);
+    db.expect_query("SELECT b FROM TestTable WHERE a = ?;", [IntValue(1)]);
+    db.expect_no_more_queries()
+}
+
+#[test]
+fn mock_adapter_schema_test() {
+    let db = MockAdapter::new();
+    db.add_table("ArchivedPost", vec![]);
+    assert!(!db.table_exists::<ArchivedPost>());
+    db.add_table("archive.ArchivedPost", vec![]);
+    assert!(db.table_exists::<ArchivedPost>());
+
+    db.drop_table::<ArchivedPost>();
+    assert!(!db.table_exists::<ArchivedPost>());
+    db.expect_query("DROP TABLE archive.ArchivedPost;", []);
+    db.expect_no_more_queries()
+}
+
+#[test]
+fn mock_adapter_insert_error_test() {
+    let db = MockAdapter::new();
+    let err = UniqueViolation { table: "UniqueTable".to_str(), columns: vec!["id".to_str()] };
+    db.fail_next_insert(err.clone());
+    assert_eq!(db.insert_many([UniqueTable { id: 1, a: 1, b: None }].iter()), Err(err));
+    assert_eq!(db.insert_many([UniqueTable { id: 1, a: 1, b: None }].iter()), Ok(()));
+    assert_eq!(db.queries().len(), 1)
+}
+
+#[test]
+fn open_in_memory_test() {
+    let db = sql::sqlite::open_in_memory().unwrap();
+    db.create_tables::<(Author, Book)>();
+    assert!(db.table_exists::<Author>());
+    assert!(db.table_exists::<Book>());
+
+    // Foreign keys are enforced like with `sql::sqlite::open`.
+    assert!(db.insert_many([Book { author: Some(1), title: "Orphan".to_str() }].iter()).is_err())
+}
+
+#[test]
+fn temp_database_test() {
+    let path = {
+        let db = sql::sqlite::TempDatabase::new().unwrap();
+        db.create_tables::<(TestTable,)>();
+        db.insert_many([TestTable { a: Some(1), b: "Hello, world!".to_str() }].iter()).unwrap();
+        let result: Vec<TestTable> = db.select_all().collect();
+        assert_eq!(result, vec![TestTable { a: Some(1), b: "Hello, world!".to_str() }]);
+        assert!(db.path().exists());
+        assert!(db.path() != sql::sqlite::TempDatabase::new().unwrap().path());
+        db.path().clone()
+    };
+    assert!(!path.exists());
+    assert!(!path.dir_path().exists())
+}
+
+#[test]
+fn pool_test() {
+    let file = sql::sqlite::TempDatabase::new().unwrap();
+    file.create_tables::<(TestTable,)>();
+
+    let mut config = PoolConfig::new();
+    config.max_size = 1;
+    config.checkout_timeout_ms = 50;
+    config.health_check = Some("SELECT 1;".to_str());
+    let busy_timeout_ms = 1000i;
+    config.set_init(format!("PRAGMA busy_timeout = {};", busy_timeout_ms));
+    let pool = Pool::new(SqliteFactory::new(file.path().as_str().unwrap()), config).unwrap();
+
+    let (tx, rx) = channel();
+    let task_pool = pool.clone();
+    spawn(proc() {
+        let db = task_pool.get().unwrap();
+        db.insert_many([TestTable { a: Some(1), b: "Hello, world!".to_str() }].iter()).unwrap();
+        tx.send(());
+    });
+    rx.recv();
+
+    {
+        let db = pool.get().unwrap();
+        assert!(pool.get().err() == Some(CheckoutTimeout));
+        let result: Vec<TestTable> = db.select_all().collect();
+        assert_eq!(result, vec![TestTable { a: Some(1), b: "Hello, world!".to_str() }]);
+    }
+    assert_eq!(pool.size(), 1);
+    assert_eq!(pool.idle_count(), 1)
+}
+
+#[test]
+fn pool_min_idle_test() {
+    let file = sql::sqlite::TempDatabase::new().unwrap();
+    let mut config = PoolConfig::new();
+    config.max_size = 2;
+    config.min_idle = 1;
+    let pool = Pool::new(SqliteFactory::new(file.path().as_str().unwrap()), config).unwrap();
+    assert_eq!(pool.idle_count(), 1);
+
+    {
+        let _db = pool.get().unwrap();
+        assert_eq!(pool.size(), 2);
+        assert_eq!(pool.idle_count(), 1);
+    }
+    assert_eq!(pool.idle_count(), 2);
+
+    // A connection whose task failed may be left in a transaction.
+    let task_pool = pool.clone();
+    let result = task::try::<()>(proc() {
+        let _db = task_pool.get().unwrap();
+        fail!("holder failed")
+    });
+    assert!(result.is_err());
+    assert_eq!(pool.size(), 1);
+    assert_eq!(pool.idle_count(), 1);
+
+    let mut config = PoolConfig::new();
+    config.max_size = 1;
+    config.min_idle = 2;
+    assert!(Pool::new(SqliteFactory::new(file.path().as_str().unwrap()), config).is_err())
+}
+
+#[test]
+fn shared_database_test() {
+    let file = sql::sqlite::TempDatabase::new().unwrap();
+    let shared = sql::sqlite::open_shared(file.path().as_str().unwrap()).unwrap();
+    shared.create_table_if_not_exists::<TestTable>().unwrap();
+
+    let (tx, rx) = channel();
+    for i in range(0i, 3) {
+        let (db, tx) = (shared.clone(), tx.clone());
+        spawn(proc() {
+            tx.send(db.insert_many([TestTable { a: Some(i), b: "Hello, world!".to_str() }].iter()));
+        });
+    }
+    for _ in range(0i, 3) {
+        assert_eq!(rx.recv(), Ok(()));
+    }
+
+    assert_eq!(shared.select_all::<TestTable>().unwrap().len(), 3);
+    let wanted = 1i;
+    let result = shared.fetch(sql!(select * from TestTable where a = $wanted));
+    assert_eq!(result, Ok(vec![TestTable { a: Some(1), b: "Hello, world!".to_str() }]));
+    let columns = shared.fetch_columns(sql!(select b from TestTable where a = $wanted));
+    assert_eq!(columns, Ok(vec![("Hello, world!".to_str(),)]));
+    assert!(shared.execute("SELECT * FROM NoSuchTable;").is_err());
+
+    // The table doesn't exist in this database, so preparing the select fails
+    // the database task, and no other connection is opened in its place.
+    assert!(shared.select_all::<OtherTable>().is_err());
+    assert!(shared.select_all::<TestTable>().is_err());
+    assert!(shared.execute("SELECT 1;").is_err())
+}
+
+#[test]
+fn statement_cache_test() {
+    let db = sql::sqlite::open_with_capacity(":memory:", 2).unwrap();
+    db.create_table_if_not_exists::<TestTable>();
+    db.insert_many([TestTable { a: Some(1), b: "Foo".to_str() }].iter()).unwrap();
+    db.insert_many([TestTable { a: Some(2), b: "Bar".to_str() }].iter()).unwrap();
+    for _ in range(0i, 2) {
+        let result: Vec<TestTable> = db.select_all().collect();
+        assert_eq!(result.len(), 2);
+    }
+    assert_eq!(db.cache_stats(), CacheStats { hits: 2, misses: 2, evictions: 0 });
+
+    let wanted = 2i;
+    let rows: Vec<(String,)> = sql!(select b from TestTable where a = $wanted).fetch(&db).collect();
+    assert_eq!(rows, vec![("Bar".to_str(),)]);
+    assert_eq!(db.cache_stats(), CacheStats { hits: 2, misses: 3, evictions: 1 })
+}
+
+struct EventLog {
+    events: RefCell<Vec<QueryEvent>>
+}
+
+impl QueryLogger for EventLog {
+    fn log(&self, event: &QueryEvent) {
+        self.events.borrow_mut().push(event.clone())
+    }
+}
+
+#[test]
+fn traced_adapter_test() {
+    let mut db = TracedAdapter::new(MockAdapter::new(), EventLog { events: RefCell::new(Vec::new()) });
+    db.redact("TestTable.b");
+    db.insert_many([TestTable { a: Some(1), b: "secret".to_str() },
+                    TestTable { a: None, b: "secret".to_str() }].iter()).unwrap();
+
+    db.push_rows(vec![vec![IntValue(1), TextValue("Foo".to_str())], vec![NullValue, TextValue("Bar".to_str())]]);
+    let wanted = 1i;
+    let rows: Vec<(String,)> = sql!(select b from TestTable where a >= $wanted).fetch(&db).collect();
+    assert_eq!(rows.len(), 2);
+
+    let events = db.logger().events.borrow();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events.get(0).query.as_slice(), "INSERT INTO TestTable (a, b) VALUES (?, ?);");
+    assert_eq!(events.get(0).params, vec![Some(IntValue(1)), None, Some(NullValue), None]);
+    assert_eq!(events.get(0).rows, Some(2));
+    assert_eq!(events.get(1).query.as_slice(), "SELECT b FROM TestTable WHERE a >= ?;");
+    assert_eq!(events.get(1).params, vec![Some(IntValue(1))]);
+    assert_eq!(events.get(1).rows, Some(2));
+    assert_eq!(events.get(1).outcome, Succeeded)
+}
+
+#[test]
+fn traced_redaction_test() {
+    let mut db = TracedAdapter::new(MockAdapter::new(), EventLog { events: RefCell::new(Vec::new()) });
+    db.redact("TestTable.b");
+    let (wanted, secret) = (1i, "secret".to_str());
+    let _: Vec<(Option<int>,)> = sql!(select a from TestTable where a = $wanted and b = $secret).fetch(&db).collect();
+    db.execute_params("UPDATE TestTable SET b = ? WHERE a = ?;", [TextValue("secret".to_str()), IntValue(1)]);
+    db.execute_params("DELETE FROM TestTable WHERE b IN (?);", [TextValue("secret".to_str())]);
+    db.execute_params("DELETE FROM OtherTable WHERE c IN (?);", [IntValue(1)]);
+
+    let events = db.logger().events.borrow();
+    assert_eq!(events.get(0).params, vec![Some(IntValue(1)), None]);
+    assert_eq!(events.get(1).params, vec![None, Some(IntValue(1))]);
+    // Parameters not compared to a column are hidden in queries mentioning it.
+    assert_eq!(events.get(2).params, vec![None]);
+    assert_eq!(events.get(3).params, vec![Some(IntValue(1))])
+}
+
+struct ChannelLog {
+    events: Sender<QueryEvent>
+}
+
+impl QueryLogger for ChannelLog {
+    fn log(&self, event: &QueryEvent) {
+        self.events.send(event.clone())
+    }
+}
+
+#[test]
+fn traced_failure_test() {
+    let (tx, rx) = channel();
+    let db = TracedAdapter::new(sql::sqlite::open_in_memory().unwrap(), ChannelLog { events: tx.clone() });
+    assert!(db.try_execute("SELECT * FROM NoSuchTable;").is_err());
+    match rx.recv().outcome {
+        Failed(msg) => assert!(msg.as_slice().contains("NoSuchTable")),
+        outcome => fail!("unexpected outcome {}", outcome)
+    }
+
+    let result = task::try::<()>(proc() {
+        let db = TracedAdapter::new(sql::sqlite::open_in_memory().unwrap(), ChannelLog { events: tx });
+        db.execute_params("INSERT INTO NoSuchTable VALUES (?);", [IntValue(1)]);
+    });
+    assert!(result.is_err());
+    let event = rx.recv();
+    assert_eq!(event.query.as_slice(), "INSERT INTO NoSuchTable VALUES (?);");
+    assert_eq!(event.outcome, Aborted)
+}
+
+#[test]
+fn attached_schema_test() {
+    assert_eq!(sql::create_table_query::<ArchivedPost>().as_slice(),
+               "CREATE TABLE IF NOT EXISTS archive.ArchivedPost (id int not null, title text not null);");
+    assert_eq!(sql::create_index_queries::<ArchivedPost>(),
+               vec!["CREATE INDEX IF NOT EXISTS archive.ArchivedPost_title_idx ON ArchivedPost (title);".to_str()]);
+
+    let db = sql::sqlite::open_in_memory().unwrap();
+    db.attach(":memory:", "archive").unwrap();
+    db.create_table_if_not_exists::<ArchivedPost>();
+    assert!(db.table_exists::<ArchivedPost>());
+    assert!(db.table_columns("ArchivedPost").is_empty());
+    assert!(db.verify_schema::<ArchivedPost>().is_empty());
+
+    db.insert_many([ArchivedPost { id: 1, title: "Hello, world!".to_str() }].iter()).unwrap();
+    let wanted = 1i;
+    let selector = sql!(select title from ArchivedPost where id = $wanted);
+    assert_eq!(selector.query(), "SELECT title FROM archive.ArchivedPost WHERE id = ?;");
+    let rows: Vec<(String,)> = selector.fetch(&db).collect();
+    assert_eq!(rows, vec![("Hello, world!".to_str(),)]);
+
+    db.detach("archive").unwrap();
+    assert!(db.detach("archive").is_err());
+    assert!(db.attach(":memory:", "x; DROP TABLE Author").is_err())
+}
+
+#[test]
+fn join_test() {
+    let db = sql::sqlite::open_in_memory().unwrap();
+    db.create_tables::<(Author, Book)>();
+    db.insert_many([Author { id: 1, name: "Kim".to_str() }].iter()).unwrap();
+    db.insert_many([Book { author: Some(1), title: "Rust".to_str() },
+                    Book { author: None, title: "Anonymous".to_str() }].iter()).unwrap();
+
+    let name = "Kim".to_str();
+    let selector = sql!(select Book.title, Author.name from Book join Author on Book.author = Author.id
+                        where Author.name = $name);
+    assert_eq!(selector.query(),
+               "SELECT Book.title, Author.name FROM Book JOIN Author ON Book.author = Author.id WHERE Author.name = ?;");
+    let rows: Vec<(String, String)> = selector.fetch(&db).collect();
+    assert_eq!(rows, vec![("Rust".to_str(), "Kim".to_str())]);
+
+    // Joins across attached databases qualify each table with its schema.
+    db.attach(":memory:", "archive").unwrap();
+    db.create_table_if_not_exists::<ArchivedPost>();
+    db.insert_many([ArchivedPost { id: 1, title: "Hello, world!".to_str() }].iter()).unwrap();
+    let selector = sql!(select ArchivedPost.title, Author.name from ArchivedPost
+                        join Author on ArchivedPost.id = Author.id);
+    assert_eq!(selector.query(),
+               "SELECT ArchivedPost.title, Author.name FROM archive.ArchivedPost JOIN Author ON ArchivedPost.id = Author.id;");
+    let rows: Vec<(String, String)> = selector.fetch(&db).collect();
+    assert_eq!(rows, vec![("Hello, world!".to_str(), "Kim".to_str())])
+}
+
+#[test]
+fn runtime_schema_test() {
+    let db = sql::sqlite::open_in_memory().unwrap();
+    db.attach(":memory:", "scratch").unwrap();
+    assert!(db.set_schema::<ScratchNote>(Some("scratch; DROP TABLE TestTable")).is_err());
+
+    db.set_schema::<ScratchNote>(Some("scratch")).unwrap();
+    assert_eq!(sql::dialect::insert_query::<ScratchNote>(db.dialect(), db.schema::<ScratchNote>()).as_slice(),
+               "INSERT INTO scratch.ScratchNote (body) VALUES (?);");
+    db.create_table_if_not_exists::<ScratchNote>();
+    db.insert_many([ScratchNote { body: "Hello".to_str() }].iter()).unwrap();
+    let selector = sql!(select body from ScratchNote);
+    assert_eq!(selector.buf().query_on(&db).as_slice(), "SELECT body FROM scratch.ScratchNote;");
+    assert_eq!(selector.fetch(&db).collect::<Vec<(String,)>>(), vec![("Hello".to_str(),)]);
+    assert!(db.table_columns("ScratchNote").is_empty());
+
+    // The binding is the connection's own.
+    let other = sql::sqlite::open_in_memory().unwrap();
+    assert_eq!(selector.buf().query_on(&other).as_slice(), "SELECT body FROM ScratchNote;");
+    assert_eq!(sql::insert_query::<ScratchNote>().as_slice(), "INSERT INTO ScratchNote (body) VALUES (?);");
+
+    db.reset_schema::<ScratchNote>();
+    assert!(!db.table_exists::<ScratchNote>())
+}